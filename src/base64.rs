@@ -0,0 +1,86 @@
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encodes `bytes` in standard base64, which OSC 52 carries the clipboard in.
+pub fn encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, &byte)| {
+            group | (byte as u32) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            encoded.push(match i <= chunk.len() {
+                true => ALPHABET[(group >> (18 - 6 * i) & 0x3f) as usize].into(),
+                false => '=',
+            });
+        }
+    }
+    encoded
+}
+
+/// Decodes `text`, ignoring its padding, or returns `None` if it is not base64.
+pub fn decode(text: &[u8]) -> Option<Vec<u8>> {
+    let text = text
+        .strip_suffix(b"==")
+        .or(text.strip_suffix(b"="))
+        .unwrap_or(text);
+    let mut decoded = Vec::with_capacity(text.len() / 4 * 3 + 2);
+    for chunk in text.chunks(4) {
+        if chunk.len() == 1 {
+            return None;
+        }
+        let mut group = 0;
+        for (i, &c) in chunk.iter().enumerate() {
+            let value = ALPHABET.iter().position(|&a| a == c)? as u32;
+            group |= value << (18 - 6 * i);
+        }
+        decoded.extend(&group.to_be_bytes()[1..chunk.len()]);
+    }
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encoding_pads_to_groups_of_four() {
+        for (bytes, encoded) in [
+            (&b""[..], ""),
+            (b"f", "Zg=="),
+            (b"fo", "Zm8="),
+            (b"foo", "Zm9v"),
+            (b"foob", "Zm9vYg=="),
+            (b"fooba", "Zm9vYmE="),
+            (b"foobar", "Zm9vYmFy"),
+            (&[0xfb, 0xff, 0xbf], "+/+/"),
+        ] {
+            assert_eq!(encode(bytes), encoded);
+            assert_eq!(decode(encoded.as_bytes()).as_deref(), Some(bytes));
+        }
+    }
+
+    #[test]
+    fn every_byte_round_trips() {
+        let bytes: Vec<u8> = (0..=255).collect();
+        for length in 0..bytes.len() {
+            let bytes = &bytes[length..];
+            assert_eq!(decode(encode(bytes).as_bytes()).as_deref(), Some(bytes));
+        }
+    }
+
+    #[test]
+    fn padding_is_optional() {
+        assert_eq!(decode(b"Zg").as_deref(), Some(&b"f"[..]));
+        assert_eq!(decode(b"Zm8").as_deref(), Some(&b"fo"[..]));
+        assert_eq!(decode(b"Zm9vYg").as_deref(), Some(&b"foob"[..]));
+    }
+
+    #[test]
+    fn invalid_input_is_rejected() {
+        assert_eq!(decode(b"Zm9v!A=="), None);
+        assert_eq!(decode(b"Zm 9v"), None);
+        assert_eq!(decode(b"Zm9vY"), None);
+        assert_eq!(decode(b"Z==="), None);
+        assert_eq!(decode(b"Zm=v"), None);
+    }
+}
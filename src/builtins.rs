@@ -0,0 +1,669 @@
+use std::{
+    env, fs,
+    io::{self, Write},
+    path::Path,
+};
+
+use crate::{
+    complete::Spec,
+    keymap::{self, ACTIONS, Action},
+    lexer,
+    shell::{self, Shell, Streams},
+};
+
+pub type Builtin = fn(&mut Shell, &[String], &Streams) -> io::Result<i32>;
+
+pub const BUILTINS: &[(&str, Builtin)] = &[
+    (".", dot),
+    (":", colon),
+    ("alias", alias),
+    ("bind", bind),
+    ("cd", cd),
+    ("complete", complete),
+    ("exit", exit),
+    ("export", export),
+    ("false", r#false),
+    ("fc", fc),
+    ("true", colon),
+    ("unalias", unalias),
+    ("unset", unset),
+];
+
+pub fn find(name: &str) -> Option<Builtin> {
+    BUILTINS
+        .iter()
+        .find(|(builtin, _)| *builtin == name)
+        .map(|(_, builtin)| *builtin)
+}
+
+fn colon(_: &mut Shell, _: &[String], _: &Streams) -> io::Result<i32> {
+    Ok(0)
+}
+
+fn r#false(_: &mut Shell, _: &[String], _: &Streams) -> io::Result<i32> {
+    Ok(1)
+}
+
+fn dot(shell: &mut Shell, args: &[String], streams: &Streams) -> io::Result<i32> {
+    let Some(file) = args.get(1) else {
+        writeln!(streams.stderr(), ".: filename argument required")?;
+        return Ok(2);
+    };
+    if let Err(error) = shell.source(Path::new(file), streams) {
+        writeln!(streams.stderr(), ".: {}: {}", file, error)?;
+        return Ok(1);
+    }
+    Ok(shell.status)
+}
+
+fn alias(shell: &mut Shell, args: &[String], streams: &Streams) -> io::Result<i32> {
+    let mut stdout = streams.stdout();
+    if args.len() == 1 {
+        let mut aliases: Vec<(&String, &String)> = shell.aliases.iter().collect();
+        aliases.sort();
+        for (name, value) in aliases {
+            writeln!(stdout, "alias {}={}", name, lexer::quote(value))?;
+        }
+        return Ok(0);
+    }
+
+    let mut status = 0;
+    for arg in &args[1..] {
+        match arg.split_once('=') {
+            Some((name, value)) => {
+                shell.aliases.insert(name.to_owned(), value.to_owned());
+            }
+            None => match shell.aliases.get(arg) {
+                Some(value) => writeln!(stdout, "alias {}={}", arg, lexer::quote(value))?,
+                None => {
+                    writeln!(streams.stderr(), "alias: {}: not found", arg)?;
+                    status = 1;
+                }
+            },
+        }
+    }
+    Ok(status)
+}
+
+fn unalias(shell: &mut Shell, args: &[String], streams: &Streams) -> io::Result<i32> {
+    if args.get(1).is_some_and(|arg| arg == "-a") {
+        shell.aliases.clear();
+        return Ok(0);
+    }
+
+    let mut status = 0;
+    for name in &args[1..] {
+        if shell.aliases.remove(name).is_none() {
+            writeln!(streams.stderr(), "unalias: {}: not found", name)?;
+            status = 1;
+        }
+    }
+    Ok(status)
+}
+
+/// `bind [key [action]]`, `bind -l` and `bind -r key...`.
+fn bind(shell: &mut Shell, args: &[String], streams: &Streams) -> io::Result<i32> {
+    let mut stdout = streams.stdout();
+    let args: Vec<&str> = args[1..].iter().map(String::as_str).collect();
+    match args.as_slice() {
+        [] => {
+            for (key, action) in shell.keymap.bindings() {
+                writeln!(stdout, "bind {} {}", key, action.name())?;
+            }
+        }
+        ["-l"] => {
+            for (name, _) in ACTIONS {
+                writeln!(stdout, "{}", name)?;
+            }
+        }
+        ["-r", keys @ ..] => {
+            let mut status = 0;
+            for key in keys {
+                let unbound = keymap::parse_key(key)
+                    .is_some_and(|(key, modifiers)| shell.keymap.unbind(&key, modifiers));
+                if !unbound {
+                    writeln!(streams.stderr(), "bind: {}: not bound", key)?;
+                    status = 1;
+                }
+            }
+            return Ok(status);
+        }
+        [key] | [key, _] if !key.starts_with('-') || key.len() == 1 => {
+            let Some((parsed, modifiers)) = keymap::parse_key(key) else {
+                writeln!(streams.stderr(), "bind: {}: unknown key", key)?;
+                return Ok(1);
+            };
+            match args.get(1) {
+                Some(name) => {
+                    let Some(action) = Action::find(name) else {
+                        writeln!(streams.stderr(), "bind: {}: unknown action", name)?;
+                        return Ok(1);
+                    };
+                    shell.keymap.bind(parsed, modifiers, action);
+                }
+                None => match shell.keymap.get(&parsed, modifiers) {
+                    Some(action) => writeln!(stdout, "bind {} {}", key, action.name())?,
+                    None => {
+                        writeln!(streams.stderr(), "bind: {}: not bound", key)?;
+                        return Ok(1);
+                    }
+                },
+            }
+        }
+        _ => {
+            writeln!(
+                streams.stderr(),
+                "usage: bind [key [action]] | bind -l | bind -r key ..."
+            )?;
+            return Ok(2);
+        }
+    }
+    Ok(0)
+}
+
+/// `complete [-W words] [-G pattern] [-F function] [-X pattern] name...`, `complete -p [name...]`
+/// and `complete -r [name...]`.
+fn complete(shell: &mut Shell, args: &[String], streams: &Streams) -> io::Result<i32> {
+    let mut spec = Spec::default();
+    let mut print = false;
+    let mut remove = false;
+    let mut names: Vec<&String> = Vec::new();
+
+    let mut args = args[1..].iter();
+    while let Some(arg) = args.next() {
+        let value = match arg.as_str() {
+            "-p" => {
+                print = true;
+                continue;
+            }
+            "-r" => {
+                remove = true;
+                continue;
+            }
+            "-W" => &mut spec.words,
+            "-G" => &mut spec.glob,
+            "-F" => &mut spec.function,
+            "-X" => &mut spec.filter,
+            "--" => {
+                names.extend(args.by_ref());
+                break;
+            }
+            option if option.starts_with('-') => {
+                writeln!(streams.stderr(), "complete: {}: invalid option", option)?;
+                writeln!(
+                    streams.stderr(),
+                    "usage: complete [-pr] [-W words] [-G pattern] [-F function] [-X pattern] [name ...]"
+                )?;
+                return Ok(2);
+            }
+            _ => {
+                names.push(arg);
+                continue;
+            }
+        };
+        let Some(argument) = args.next() else {
+            writeln!(
+                streams.stderr(),
+                "complete: {}: option requires an argument",
+                arg
+            )?;
+            return Ok(2);
+        };
+        *value = Some(argument.clone());
+    }
+
+    if remove {
+        if names.is_empty() {
+            shell.completions.clear();
+        }
+        for name in names {
+            shell.completions.remove(name);
+        }
+        return Ok(0);
+    }
+
+    if print || spec == Spec::default() {
+        let mut specs: Vec<(&String, &Spec)> = match names.is_empty() {
+            true => shell.completions.iter().collect(),
+            false => shell
+                .completions
+                .iter()
+                .filter(|(name, _)| names.contains(name))
+                .collect(),
+        };
+        specs.sort_by_key(|(name, _)| *name);
+        let mut stdout = streams.stdout();
+        for (name, spec) in &specs {
+            writeln!(stdout, "{}", spec.command(name))?;
+        }
+        return Ok((specs.len() < names.len()) as i32);
+    }
+
+    if names.is_empty() {
+        writeln!(streams.stderr(), "complete: command name required")?;
+        return Ok(2);
+    }
+    for name in names {
+        shell.completions.insert(name.clone(), spec.clone());
+    }
+    Ok(0)
+}
+
+fn cd(shell: &mut Shell, args: &[String], streams: &Streams) -> io::Result<i32> {
+    let directory = match args.get(1).map(String::as_str) {
+        None => shell.variables.get("HOME").map(str::to_owned),
+        Some("-") => {
+            let directory = shell.variables.get("OLDPWD").map(str::to_owned);
+            if let Some(directory) = &directory {
+                writeln!(streams.stdout(), "{}", directory)?;
+            }
+            directory
+        }
+        Some(directory) => Some(directory.to_owned()),
+    };
+    let Some(directory) = directory else {
+        writeln!(streams.stderr(), "cd: HOME not set")?;
+        return Ok(1);
+    };
+
+    // The directory may have been removed, which `PWD` still names.
+    let previous = match env::current_dir() {
+        Ok(previous) => Some(previous.to_string_lossy().into_owned()),
+        Err(_) => shell.variables.get("PWD").map(str::to_owned),
+    };
+    if let Err(error) = env::set_current_dir(&directory) {
+        writeln!(streams.stderr(), "cd: {}: {}", directory, error)?;
+        return Ok(1);
+    }
+
+    if let Some(previous) = previous {
+        shell.variables.set("OLDPWD", previous);
+    }
+    match env::current_dir() {
+        Ok(current) => shell.variables.set("PWD", current.to_string_lossy()),
+        Err(error) => {
+            writeln!(streams.stderr(), "cd: {}: {}", directory, error)?;
+            return Ok(1);
+        }
+    }
+    Ok(0)
+}
+
+fn exit(shell: &mut Shell, args: &[String], streams: &Streams) -> io::Result<i32> {
+    let status = match args.get(1) {
+        None => shell.status,
+        Some(status) => match status.parse() {
+            Ok(status) => status,
+            Err(_) => {
+                writeln!(
+                    streams.stderr(),
+                    "exit: {}: numeric argument required",
+                    status
+                )?;
+                2
+            }
+        },
+    };
+    shell.exit = Some(status);
+    Ok(status)
+}
+
+fn export(shell: &mut Shell, args: &[String], streams: &Streams) -> io::Result<i32> {
+    if args.len() == 1 || args[1] == "-p" {
+        let mut exported: Vec<(&str, &str)> = shell.variables.exported().collect();
+        exported.sort();
+        let mut stdout = streams.stdout();
+        for (name, value) in exported {
+            writeln!(stdout, "export {}={}", name, lexer::quote(value))?;
+        }
+        return Ok(0);
+    }
+
+    let mut status = 0;
+    for arg in &args[1..] {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (arg.as_str(), None),
+        };
+        if !lexer::is_name(name) {
+            writeln!(
+                streams.stderr(),
+                "export: `{}': not a valid identifier",
+                arg
+            )?;
+            status = 1;
+            continue;
+        }
+
+        if let Some(value) = value {
+            shell.variables.set(name, value);
+        }
+        shell.variables.export(name);
+    }
+    Ok(status)
+}
+
+fn unset(shell: &mut Shell, args: &[String], _: &Streams) -> io::Result<i32> {
+    let mut names = &args[1..];
+    let functions = names.first().is_some_and(|arg| arg == "-f");
+    if functions || names.first().is_some_and(|arg| arg == "-v") {
+        names = &names[1..];
+    }
+
+    for name in names {
+        if functions {
+            shell.functions.remove(name);
+        } else {
+            shell.variables.unset(name);
+        }
+    }
+    Ok(0)
+}
+
+/// `fc [-r] [-e editor] [first [last]]`, `fc -l [-nr] [first [last]]` and
+/// `fc -s [old=new] [first]`.
+fn fc(shell: &mut Shell, args: &[String], streams: &Streams) -> io::Result<i32> {
+    let mut editor: Option<String> = None;
+    let mut list = false;
+    let mut numbers = true;
+    let mut reverse = false;
+    let mut substitute = false;
+    let mut operands: Vec<&str> = Vec::new();
+
+    let mut args = args[1..].iter();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            operands.extend(args.map(String::as_str));
+            break;
+        }
+        // Negative numbers are history offsets rather than options.
+        if !operands.is_empty()
+            || !arg.starts_with('-')
+            || arg.len() == 1
+            || arg[1..].starts_with(|c: char| c.is_ascii_digit())
+        {
+            operands.push(arg);
+            continue;
+        }
+
+        let mut flags = arg[1..].chars();
+        while let Some(flag) = flags.next() {
+            match flag {
+                'e' => {
+                    editor = match flags.as_str() {
+                        "" => args.next().cloned(),
+                        rest => Some(rest.to_owned()),
+                    };
+                    if editor.is_none() {
+                        writeln!(streams.stderr(), "fc: -e: option requires an argument")?;
+                        return Ok(2);
+                    }
+                    break;
+                }
+                'l' => list = true,
+                'n' => numbers = false,
+                'r' => reverse = true,
+                's' => substitute = true,
+                _ => {
+                    writeln!(streams.stderr(), "fc: -{}: invalid option", flag)?;
+                    writeln!(
+                        streams.stderr(),
+                        "usage: fc [-e editor] [-nlr] [first [last]] or fc -s [old=new] [first]"
+                    )?;
+                    return Ok(2);
+                }
+            }
+        }
+    }
+    substitute |= editor.as_deref() == Some("-");
+
+    // The `fc` invocation itself is not one of the commands it operates on.
+    let mut entries = shell.history.entries();
+    if shell.line.is_some() && entries.last() == shell.line.as_ref() {
+        entries = &entries[..entries.len() - 1];
+    }
+    if entries.is_empty() {
+        writeln!(streams.stderr(), "fc: history is empty")?;
+        return Ok(1);
+    }
+
+    let resolve = |operand: &str| -> Option<usize> {
+        match operand.parse::<i64>() {
+            Ok(number) if number > 0 => {
+                let index = (number as usize).saturating_sub(shell.history.number(0));
+                Some(index.min(entries.len() - 1))
+            }
+            Ok(offset) => Some(
+                entries
+                    .len()
+                    .saturating_sub(offset.unsigned_abs().max(1) as usize),
+            ),
+            Err(_) => entries.iter().rposition(|entry| entry.starts_with(operand)),
+        }
+    };
+
+    if substitute {
+        let (replacement, first) = match operands.as_slice() {
+            [pair, first] => (Some(*pair), Some(*first)),
+            [operand] if operand.contains('=') => (Some(*operand), None),
+            [first] => (None, Some(*first)),
+            _ => (None, None),
+        };
+        let Some(index) = first.map_or(Some(entries.len() - 1), resolve) else {
+            writeln!(streams.stderr(), "fc: no command found")?;
+            return Ok(1);
+        };
+
+        let mut command = entries[index].clone();
+        if let Some((old, new)) = replacement.and_then(|pair| pair.split_once('=')) {
+            command = command.replacen(old, new, 1);
+        }
+
+        return rerun(shell, &command, streams);
+    }
+
+    let (first, last) = match (operands.first(), operands.get(1)) {
+        (Some(first), Some(last)) => (resolve(first), resolve(last)),
+        (Some(first), None) if list => (resolve(first), Some(entries.len() - 1)),
+        (Some(first), None) => (resolve(first), resolve(first)),
+        (None, _) if list => (
+            Some(entries.len().saturating_sub(16)),
+            Some(entries.len() - 1),
+        ),
+        (None, _) => (Some(entries.len() - 1), Some(entries.len() - 1)),
+    };
+    let (Some(first), Some(last)) = (first, last) else {
+        writeln!(streams.stderr(), "fc: no command found")?;
+        return Ok(1);
+    };
+
+    let mut indices: Vec<usize> = (first.min(last)..=first.max(last)).collect();
+    if (first > last) != reverse {
+        indices.reverse();
+    }
+
+    if list {
+        let mut stdout = streams.stdout();
+        for index in indices {
+            let command = entries[index].replace('\n', "\n\t");
+            if numbers {
+                writeln!(stdout, "{}\t{}", shell.history.number(index), command)?;
+            } else {
+                writeln!(stdout, "\t{}", command)?;
+            }
+        }
+        return Ok(0);
+    }
+
+    let content: String = indices
+        .into_iter()
+        .map(|index| entries[index].clone() + "\n")
+        .collect();
+    let (mut file, path) = match shell::create_temp_file("oxide-film-fc-", ".sh") {
+        Ok(created) => created,
+        Err(error) => {
+            writeln!(streams.stderr(), "fc: {}", error)?;
+            return Ok(1);
+        }
+    };
+    let written = file.write_all(content.as_bytes());
+    drop(file);
+
+    let editor = editor
+        .or_else(|| shell.variables.get("FCEDIT").map(str::to_owned))
+        .unwrap_or_else(|| String::from("ed"));
+    // The file is removed however the editor ends.
+    let edited = written
+        .and_then(|()| {
+            shell.execute(
+                &format!("{} {}", editor, lexer::quote(&path.to_string_lossy())),
+                &Streams::default(),
+            )
+        })
+        .map(|()| fs::read_to_string(&path));
+    // The editor may have removed it already.
+    if let Err(error) = fs::remove_file(&path)
+        && error.kind() != io::ErrorKind::NotFound
+    {
+        writeln!(streams.stderr(), "fc: {}: {}", path.display(), error)?;
+    }
+
+    if edited.is_ok() && shell.status != 0 {
+        return Ok(shell.status);
+    }
+    match edited.and_then(|edited| edited) {
+        Ok(edited) => rerun(shell, edited.trim_end_matches('\n'), streams),
+        Err(error) => {
+            writeln!(streams.stderr(), "fc: {}: {}", path.display(), error)?;
+            Ok(1)
+        }
+    }
+}
+
+/// Prints the commands produced by `fc`, enters them into the history and executes them.
+fn rerun(shell: &mut Shell, commands: &str, streams: &Streams) -> io::Result<i32> {
+    if commands.trim().is_empty() {
+        return Ok(0);
+    }
+
+    writeln!(streams.stdout(), "{}", commands)?;
+    shell.record(commands);
+    shell.execute(commands, streams)?;
+    Ok(shell.status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bind_changes_and_lists_the_keymap() {
+        let mut shell = Shell::for_tests();
+        assert_eq!(
+            shell.capture("bind ctrl-x yank; bind ctrl-x").unwrap(),
+            "bind ctrl-x yank"
+        );
+        assert_eq!(
+            shell
+                .capture("bind alt-ctrl-x abort; bind ctrl-alt-x")
+                .unwrap(),
+            "bind ctrl-alt-x abort"
+        );
+        assert!(
+            shell
+                .capture("bind")
+                .unwrap()
+                .lines()
+                .any(|line| line == "bind tab complete")
+        );
+        assert_eq!(
+            shell.capture("bind -l").unwrap().lines().count(),
+            ACTIONS.len()
+        );
+
+        assert_eq!(
+            shell.capture("bind -r ctrl-x ctrl-alt-x; echo $?").unwrap(),
+            "0"
+        );
+        assert_eq!(
+            shell.capture("bind ctrl-x 2>/dev/null; echo $?").unwrap(),
+            "1"
+        );
+        assert_eq!(
+            shell
+                .capture("bind -r ctrl-x 2>/dev/null; echo $?")
+                .unwrap(),
+            "1"
+        );
+        assert_eq!(
+            shell
+                .capture("bind ctrl-nope yank 2>/dev/null; echo $?")
+                .unwrap(),
+            "1"
+        );
+        assert_eq!(
+            shell
+                .capture("bind ctrl-x nothing 2>/dev/null; echo $?")
+                .unwrap(),
+            "1"
+        );
+        assert_eq!(shell.capture("bind -x 2>/dev/null; echo $?").unwrap(), "2");
+        assert_eq!(
+            shell.capture("bind a b c 2>/dev/null; echo $?").unwrap(),
+            "2"
+        );
+
+        // A key written as a lone `-` is not taken for an option.
+        assert_eq!(shell.capture("bind - yank; bind -").unwrap(), "bind - yank");
+    }
+
+    #[test]
+    fn cd_leaves_a_removed_directory() {
+        let mut shell = Shell::for_tests();
+        let output = shell
+            .capture(
+                "(d=$(mktemp -d); cd \"$d\"; rmdir \"$d\"; cd /; echo $?; pwd; \
+                 test \"$OLDPWD\" = \"$d\" && echo OLDPWD)",
+            )
+            .unwrap();
+        assert_eq!(output, "0\n/\nOLDPWD");
+    }
+
+    #[test]
+    fn fc_edits_a_private_file_and_removes_it() {
+        let mut shell = Shell::for_tests();
+        shell.history.push("echo one").unwrap();
+
+        // The editor replaces the commands with one printing the permissions of the file.
+        let editor = r#"sh -c 'echo "echo $(ls -l "$0" | cut -c 2-10)" > "$0"'"#;
+        let output = shell
+            .capture(&format!("fc -e {}", lexer::quote(editor)))
+            .unwrap();
+        assert_eq!(output, "echo rw-------\nrw-------");
+        // This one replaces the file rather than writing to it.
+        let editor = r#"sh -c 'sed s/w/x/ "$0" > "$0.new" && mv "$0.new" "$0"'"#;
+        let output = shell
+            .capture(&format!("fc -e {} -1", lexer::quote(editor)))
+            .unwrap();
+        assert_eq!(output, "echo rx-------\nrx-------");
+        let output = shell.capture("fc -e false").unwrap();
+        assert_eq!(output, "");
+        assert_eq!(shell.status, 1);
+
+        // An editor removing the file fails the command, not the shell.
+        let output = shell.capture("fc -e rm 2>/dev/null; echo $?").unwrap();
+        assert_eq!(output, "1");
+
+        let left = fs::read_dir(env::temp_dir())
+            .unwrap()
+            .filter_map(Result::ok)
+            .filter(|entry| {
+                entry
+                    .file_name()
+                    .to_string_lossy()
+                    .starts_with("oxide-film-fc-")
+            })
+            .count();
+        assert_eq!(left, 0);
+    }
+}
@@ -0,0 +1,725 @@
+use std::{ffi::CStr, fs, io, ops::Range, path::Path};
+
+use crate::{
+    builtins, expand,
+    lexer::{self, Kind, Token},
+    pattern,
+    shell::{self, Shell, Streams},
+};
+
+/// What the word under the cursor stands for.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Context {
+    /// The name of the command to run.
+    Command,
+    /// An argument of the named command.
+    Argument(String),
+    /// A variable name following `$` or `${`.
+    Variable,
+    /// A user name following `~`.
+    User,
+    /// The file a redirection applies to.
+    Redirection,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Candidate {
+    /// The name shown in the list of candidates.
+    pub display: String,
+    /// The quoted text replacing the completed range of the line.
+    pub replacement: String,
+    /// Appended after the replacement when the candidate is the only one.
+    pub suffix: String,
+}
+
+#[derive(Debug)]
+pub struct Completion {
+    pub context: Context,
+    /// The byte range of the line that the candidates replace.
+    pub range: Range<usize>,
+    pub candidates: Vec<Candidate>,
+}
+
+impl Completion {
+    /// The text to insert in place of the range: the candidate followed by its suffix if it is
+    /// the only one, the longest prefix shared by the candidates otherwise.
+    pub fn insertion(&self) -> Option<String> {
+        let (first, rest) = self.candidates.split_first()?;
+        if rest.is_empty() {
+            return Some(format!("{}{}", first.replacement, first.suffix));
+        }
+
+        let mut prefix = first.replacement.as_str();
+        for candidate in rest {
+            let length = prefix
+                .char_indices()
+                .zip(candidate.replacement.chars())
+                .find(|((_, a), b)| a != b)
+                .map_or(
+                    prefix.len().min(candidate.replacement.len()),
+                    |((i, _), _)| i,
+                );
+            prefix = &prefix[..length];
+        }
+        Some(prefix.to_owned())
+    }
+}
+
+/// How the arguments of a command are completed, as registered by the `complete` builtin.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Spec {
+    /// Words, expanded when completing, among which the candidates are picked (`-W`).
+    pub words: Option<String>,
+    /// A pattern whose matching paths are candidates (`-G`).
+    pub glob: Option<String>,
+    /// A function printing words one per line (`-F`), called with the command name, the word
+    /// being completed and the word before it.
+    pub function: Option<String>,
+    /// A pattern removing the matching candidates, or keeping only them after a `!` (`-X`).
+    pub filter: Option<String>,
+}
+
+impl Spec {
+    /// The `complete` command registering this specification for `name`.
+    pub fn command(&self, name: &str) -> String {
+        let mut command = String::from("complete");
+        for (option, value) in [
+            ("-W", &self.words),
+            ("-G", &self.glob),
+            ("-F", &self.function),
+            ("-X", &self.filter),
+        ] {
+            if let Some(value) = value {
+                command = format!("{} {} {}", command, option, lexer::quote(value));
+            }
+        }
+        format!("{} {}", command, lexer::quote(name))
+    }
+}
+
+/// Characters that a completed word quotes with a backslash.
+const SPECIAL: &str = " \t\n'\"\\$`&|;<>()*?[]{}!";
+
+/// Completes the word ending at the byte offset `cursor` of `line`.
+pub fn complete(shell: &mut Shell, line: &str, cursor: usize) -> io::Result<Completion> {
+    let tokens = lexer::tokenize(&line[..cursor]);
+    let (word, previous) = match tokens.split_last() {
+        Some((last, previous))
+            if matches!(last.kind, Kind::Word | Kind::Comment) && last.span.end == cursor =>
+        {
+            (Some(last), previous)
+        }
+        _ => (None, tokens.as_slice()),
+    };
+
+    let mut completion = Completion {
+        context: context(previous),
+        range: cursor..cursor,
+        candidates: Vec::new(),
+    };
+    let raw = match word {
+        Some(token) if token.kind == Kind::Word => {
+            completion.range.start = token.span.start;
+            token.text.as_str()
+        }
+        // Comments are not completed.
+        Some(_) => return Ok(completion),
+        None => "",
+    };
+
+    if let Some((start, braced)) = variable(raw) {
+        completion.context = Context::Variable;
+        completion.range.start += start;
+        let prefix = &raw[start..];
+        completion.candidates = shell
+            .variables
+            .names()
+            .filter(|name| name.starts_with(prefix))
+            .map(|name| Candidate {
+                display: name.to_owned(),
+                replacement: name.to_owned(),
+                suffix: String::from(if braced { "}" } else { "" }),
+            })
+            .collect();
+    } else if let Some(prefix) = raw
+        .strip_prefix('~')
+        .filter(|user| !user.contains(['/', '\'', '"', '\\', '$', '`']))
+    {
+        completion.context = Context::User;
+        completion.range.start += 1;
+        completion.candidates = users(prefix)
+            .into_iter()
+            .map(|user| Candidate {
+                display: user.clone(),
+                replacement: user,
+                suffix: String::from("/"),
+            })
+            .collect();
+    } else {
+        let typed = Typed::new(shell, raw);
+        completion.candidates = match &completion.context {
+            Context::Command if typed.slash.is_none() => commands(shell, &typed),
+            Context::Command => files(&typed, true),
+            Context::Argument(command) => {
+                let command = command.clone();
+                let previous = previous.last().map_or("", |token| token.text.as_str());
+                let mut candidates = match spec(shell, &command)? {
+                    Some(spec) => programmed(shell, &spec, &command, &typed, previous)?,
+                    None => Vec::new(),
+                };
+                if candidates.is_empty() {
+                    candidates = files(&typed, false);
+                }
+                candidates
+            }
+            _ => files(&typed, false),
+        };
+    }
+
+    completion
+        .candidates
+        .sort_by(|a, b| a.display.cmp(&b.display));
+    completion
+        .candidates
+        .dedup_by(|a, b| a.replacement == b.replacement);
+    Ok(completion)
+}
+
+/// Returns the completion specification of `command`, first looking for a file defining it in
+/// the `completions` directory of the configuration.
+fn spec(shell: &mut Shell, command: &str) -> io::Result<Option<Spec>> {
+    let name = command.rsplit('/').next().unwrap_or(command);
+    if !shell.completions.contains_key(name) && shell.searched_completions.insert(name.to_owned()) {
+        let file = shell.config.home.join("completions").join(name);
+        if file.is_file() {
+            shell.source(&file, &Streams::default())?;
+        }
+    }
+
+    Ok(shell
+        .completions
+        .get(command)
+        .or_else(|| shell.completions.get(name))
+        .cloned())
+}
+
+/// Generates the candidates of a completion specification.
+fn programmed(
+    shell: &mut Shell,
+    spec: &Spec,
+    command: &str,
+    typed: &Typed,
+    previous: &str,
+) -> io::Result<Vec<Candidate>> {
+    let prefix = typed.text.as_str();
+    let mut words: Vec<(String, bool)> = Vec::new();
+
+    if let Some(list) = &spec.words {
+        for token in lexer::tokenize(list) {
+            if token.kind == Kind::Word {
+                for word in expand::fields(shell, &token.text)? {
+                    if word.starts_with(prefix) {
+                        words.push((word, false));
+                    }
+                }
+            }
+        }
+    }
+
+    if let Some(glob) = &spec.glob {
+        for path in pattern::glob(glob) {
+            if path.starts_with(prefix) {
+                let directory = Path::new(&path).is_dir();
+                words.push((path, directory));
+            }
+        }
+    }
+
+    if let Some(function) = &spec.function {
+        let output = shell.capture(&format!(
+            "{} {} {} {}",
+            function,
+            lexer::quote(command),
+            lexer::quote(prefix),
+            lexer::quote(previous)
+        ))?;
+        words.extend(
+            output
+                .lines()
+                .filter(|line| !line.is_empty() && line.starts_with(prefix))
+                .map(|line| (line.to_owned(), false)),
+        );
+    }
+
+    if let Some(filter) = &spec.filter {
+        let (keep, filter) = match filter.strip_prefix('!') {
+            Some(filter) => (true, filter),
+            None => (false, filter.as_str()),
+        };
+        words.retain(|(word, _)| pattern::matches(filter, word) == keep);
+    }
+
+    Ok(words
+        .iter()
+        .map(|(word, directory)| typed.word(word, *directory))
+        .collect())
+}
+
+/// Works out what the next word of the line is from the tokens preceding it.
+fn context(tokens: &[Token]) -> Context {
+    let mut command: Option<&str> = None;
+    let mut redirection = false;
+    for token in tokens {
+        match token.kind {
+            Kind::Operator => {
+                command = None;
+                redirection = false;
+            }
+            Kind::Redirection => redirection = true,
+            Kind::Word if redirection => redirection = false,
+            Kind::Word if command.is_none() => {
+                if !lexer::COMMAND_PREFIXES.contains(&token.text.as_str())
+                    && !lexer::is_assignment(&token.text)
+                {
+                    command = Some(&token.text);
+                }
+            }
+            Kind::Word | Kind::Comment => {}
+        }
+    }
+
+    match (redirection, command) {
+        (true, _) => Context::Redirection,
+        (false, None) => Context::Command,
+        (false, Some(command)) => Context::Argument(command.to_owned()),
+    }
+}
+
+/// Finds a variable name being typed at the end of `word`, returning where the name starts
+/// and whether it follows `${`.
+fn variable(word: &str) -> Option<(usize, bool)> {
+    let mut dollar = None;
+    let mut quote = None;
+    let mut chars = word.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match (quote, c) {
+            (None, '\'' | '"') => quote = Some(c),
+            (Some(open), _) if open == c => quote = None,
+            (None | Some('"'), '\\') => {
+                chars.next();
+            }
+            (None | Some('"'), '$') => dollar = Some(i),
+            _ => {}
+        }
+    }
+
+    let dollar = dollar?;
+    let (start, braced) = match word[dollar + 1..].starts_with('{') {
+        true => (dollar + 2, true),
+        false => (dollar + 1, false),
+    };
+    let name = &word[start..];
+    (name.is_empty() || lexer::is_name(name)).then_some((start, braced))
+}
+
+/// The word under the cursor with its quotes removed, its parameters expanded and its
+/// leading tilde replaced by the home directory.
+struct Typed<'a> {
+    raw: &'a str,
+    text: String,
+    /// The offsets in `raw` and `text` just after the last slash, which ends the directory.
+    slash: Option<(usize, usize)>,
+    /// The quote left open at the end of the word, with its offset in `raw`.
+    open: Option<(char, usize)>,
+}
+
+impl<'a> Typed<'a> {
+    fn new(shell: &Shell, raw: &'a str) -> Self {
+        let mut typed = Self {
+            raw,
+            text: String::new(),
+            slash: None,
+            open: None,
+        };
+
+        let mut i = 0;
+        if let Some(rest) = raw.strip_prefix('~')
+            && let Some(end) = rest.find('/')
+        {
+            let user = &rest[..end];
+            let home = match user {
+                "" => shell.parameter("HOME"),
+                user => expand::home_directory(user),
+            };
+            if let Some(home) = home {
+                typed.text.push_str(home.trim_end_matches('/'));
+                i = 1 + end;
+            }
+        }
+
+        while let Some(c) = raw[i..].chars().next() {
+            let quote = typed.open.map(|(quote, _)| quote);
+            i += c.len_utf8();
+            match (quote, c) {
+                (Some('\''), '\'') | (Some('"'), '"') => typed.open = None,
+                (None, '\'' | '"') => typed.open = Some((c, i - 1)),
+                (None, '\\') | (Some('"'), '\\') => {
+                    if let Some(next) = raw[i..].chars().next() {
+                        if quote.is_some() && !"$`\"\\".contains(next) {
+                            typed.text.push('\\');
+                        }
+                        i += next.len_utf8();
+                        typed.push(next, i);
+                    }
+                }
+                (None | Some('"'), '$') => {
+                    let rest = &raw[i..];
+                    let (name, length) = match rest.strip_prefix('{') {
+                        Some(braced) => match braced.find('}') {
+                            Some(end) => (&braced[..end], end + 2),
+                            None => ("", 0),
+                        },
+                        None => {
+                            let end = rest
+                                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                                .unwrap_or(rest.len());
+                            (&rest[..end], end)
+                        }
+                    };
+                    if name.is_empty() {
+                        typed.push('$', i);
+                    } else {
+                        typed
+                            .text
+                            .push_str(&shell.parameter(name).unwrap_or_default());
+                        i += length;
+                    }
+                }
+                _ => typed.push(c, i),
+            }
+        }
+
+        typed
+    }
+
+    fn push(&mut self, c: char, end: usize) {
+        self.text.push(c);
+        if c == '/' {
+            self.slash = Some((end, self.text.len()));
+        }
+    }
+
+    /// The directory part of the word as typed, and as a path.
+    fn directory(&self) -> (&str, &str) {
+        match self.slash {
+            Some((raw, text)) => (&self.raw[..raw], &self.text[..text]),
+            None => ("", ""),
+        }
+    }
+
+    fn base(&self) -> &str {
+        &self.text[self.slash.map_or(0, |(_, text)| text)..]
+    }
+
+    /// Builds the candidate replacing the file name with `name`, in the typed directory.
+    fn candidate(&self, name: &str, directory: bool) -> Candidate {
+        let (raw_directory, _) = self.directory();
+        self.replacement(raw_directory, name, directory)
+    }
+
+    /// Builds the candidate replacing the whole word with `word`.
+    fn word(&self, word: &str, directory: bool) -> Candidate {
+        self.replacement("", word, directory)
+    }
+
+    fn replacement(&self, raw_directory: &str, name: &str, directory: bool) -> Candidate {
+        let mut replacement = raw_directory.to_owned();
+        let quote = self.open.map(|(quote, _)| quote);
+        // A quote opened in the file name is opened again before the completed name.
+        if let Some((quote, start)) = self.open
+            && start >= raw_directory.len()
+        {
+            replacement.push(quote);
+        }
+        replacement.push_str(&escape(name, quote, raw_directory.is_empty()));
+
+        let suffix = match (directory, quote) {
+            (true, _) => String::from("/"),
+            (false, Some(quote)) => format!("{} ", quote),
+            (false, None) => String::from(" "),
+        };
+        Candidate {
+            display: match directory {
+                true => format!("{}/", name),
+                false => name.to_owned(),
+            },
+            replacement,
+            suffix,
+        }
+    }
+}
+
+/// Quotes the special characters of `name`, inside the given quotes if any.
+fn escape(name: &str, quote: Option<char>, leading: bool) -> String {
+    match quote {
+        Some('\'') => name.replace('\'', "'\\''"),
+        Some(_) => name
+            .chars()
+            .flat_map(|c| match "\"\\$`".contains(c) {
+                true => vec!['\\', c],
+                false => vec![c],
+            })
+            .collect(),
+        None => {
+            let mut escaped = String::with_capacity(name.len());
+            for (i, c) in name.chars().enumerate() {
+                // `~` and `#` are only special at the start of a word.
+                if SPECIAL.contains(c) || (leading && i == 0 && matches!(c, '~' | '#')) {
+                    escaped.push('\\');
+                }
+                escaped.push(c);
+            }
+            escaped
+        }
+    }
+}
+
+fn commands(shell: &Shell, typed: &Typed) -> Vec<Candidate> {
+    let prefix = typed.base();
+    let mut names: Vec<String> = builtins::BUILTINS
+        .iter()
+        .map(|(name, _)| *name)
+        .chain(lexer::RESERVED_WORDS.iter().copied())
+        .chain(shell.functions.keys().map(String::as_str))
+        .chain(shell.aliases.keys().map(String::as_str))
+        .filter(|name| name.starts_with(prefix))
+        .map(str::to_owned)
+        .collect();
+
+    for directory in shell.variables.get("PATH").unwrap_or_default().split(':') {
+        let directory = Path::new(if directory.is_empty() { "." } else { directory });
+        let Ok(entries) = fs::read_dir(directory) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with(prefix) && shell::is_executable(&entry.path()) {
+                names.push(name);
+            }
+        }
+    }
+
+    names
+        .iter()
+        .map(
+            |name| match lexer::RESERVED_WORDS.contains(&name.as_str()) {
+                // Quoted, a reserved word would be taken for the name of a command.
+                true if typed.open.is_none() => Candidate {
+                    display: name.clone(),
+                    replacement: name.clone(),
+                    suffix: String::from(" "),
+                },
+                _ => typed.candidate(name, false),
+            },
+        )
+        .collect()
+}
+
+/// Lists the files of the typed directory whose names start with the typed base name; only
+/// directories and executables when completing a command name.
+fn files(typed: &Typed, executables: bool) -> Vec<Candidate> {
+    let (_, directory) = typed.directory();
+    let prefix = typed.base();
+    let Ok(entries) = fs::read_dir(if directory.is_empty() { "." } else { directory }) else {
+        return Vec::new();
+    };
+
+    let mut candidates = Vec::new();
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        // Hidden files are only completed when the name being typed starts with a dot.
+        if !name.starts_with(prefix) || (name.starts_with('.') && !prefix.starts_with('.')) {
+            continue;
+        }
+
+        let path = entry.path();
+        let is_directory = path.is_dir();
+        if executables && !is_directory && !shell::is_executable(&path) {
+            continue;
+        }
+        candidates.push(typed.candidate(&name, is_directory));
+    }
+    candidates
+}
+
+/// Lists the users whose names start with `prefix`.
+fn users(prefix: &str) -> Vec<String> {
+    let mut users = Vec::new();
+    unsafe {
+        libc::setpwent();
+        loop {
+            let passwd = libc::getpwent();
+            if passwd.is_null() {
+                break;
+            }
+            let name = CStr::from_ptr((*passwd).pw_name).to_string_lossy();
+            if name.starts_with(prefix) {
+                users.push(name.into_owned());
+            }
+        }
+        libc::endpwent();
+    }
+    users
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+
+    fn context_of(line: &str) -> Context {
+        context(&lexer::tokenize(line))
+    }
+
+    fn complete_line(shell: &mut Shell, line: &str) -> Completion {
+        complete(shell, line, line.len()).unwrap()
+    }
+
+    fn replacements(completion: &Completion) -> Vec<&str> {
+        completion
+            .candidates
+            .iter()
+            .map(|candidate| candidate.replacement.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn the_context_follows_the_tokens_before_the_word() {
+        assert_eq!(context_of(""), Context::Command);
+        assert_eq!(context_of("ls -l; "), Context::Command);
+        assert_eq!(context_of("true && "), Context::Command);
+        assert_eq!(context_of("if "), Context::Command);
+        assert_eq!(context_of("X=1 "), Context::Command);
+        assert_eq!(context_of("ls "), Context::Argument(String::from("ls")));
+        assert_eq!(
+            context_of("X=1 git commit "),
+            Context::Argument(String::from("git"))
+        );
+        assert_eq!(context_of("sort < "), Context::Redirection);
+        assert_eq!(
+            context_of("sort < in "),
+            Context::Argument(String::from("sort"))
+        );
+        assert_eq!(context_of("> out "), Context::Command);
+    }
+
+    #[test]
+    fn variables_and_paths_are_completed_after_their_markers() {
+        let mut shell = Shell::for_tests();
+        shell.variables.set("OXIDE_ONE", "");
+        shell.variables.set("OXIDE_TWO", "");
+
+        let completion = complete_line(&mut shell, "echo $OXIDE_");
+        assert_eq!(completion.context, Context::Variable);
+        assert_eq!(completion.range, 6..12);
+        assert_eq!(replacements(&completion), ["OXIDE_ONE", "OXIDE_TWO"]);
+        assert_eq!(completion.candidates[0].suffix, "");
+
+        let completion = complete_line(&mut shell, "echo \"${OXIDE_O");
+        assert_eq!(completion.context, Context::Variable);
+        assert_eq!(completion.insertion().as_deref(), Some("OXIDE_ONE}"));
+
+        // A `$` that is quoted starts no variable.
+        let completion = complete_line(&mut shell, "echo '$OXIDE_");
+        assert_eq!(completion.context, Context::Argument(String::from("echo")));
+
+        let directory = env::temp_dir().join(format!("oxide-film-complete-{}", process::id()));
+        fs::create_dir_all(directory.join("sub dir")).unwrap();
+        fs::write(directory.join("some file"), "").unwrap();
+        fs::write(directory.join(".hidden"), "").unwrap();
+        shell.variables.set("DIR", directory.to_string_lossy());
+
+        let completion = complete_line(&mut shell, "cat $DIR/s");
+        assert_eq!(completion.range, 4..10);
+        assert_eq!(
+            replacements(&completion),
+            ["$DIR/some\\ file", "$DIR/sub\\ dir"]
+        );
+        assert_eq!(
+            completion
+                .candidates
+                .iter()
+                .map(|candidate| candidate.suffix.as_str())
+                .collect::<Vec<_>>(),
+            [" ", "/"]
+        );
+
+        let completion = complete_line(&mut shell, "cat < \"$DIR/.");
+        assert_eq!(completion.context, Context::Redirection);
+        assert_eq!(replacements(&completion), ["\"$DIR/.hidden"]);
+        assert_eq!(completion.insertion().as_deref(), Some("\"$DIR/.hidden\" "));
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn candidates_are_quoted_as_the_word_is() {
+        assert_eq!(escape("a b&c", None, true), "a\\ b\\&c");
+        assert_eq!(escape("~x#y", None, true), "\\~x#y");
+        assert_eq!(escape("~x", None, false), "~x");
+        assert_eq!(escape("it's", Some('\''), true), "it'\\''s");
+        assert_eq!(
+            escape("a \"$b\" `c`", Some('"'), true),
+            "a \\\"\\$b\\\" \\`c\\`"
+        );
+    }
+
+    #[test]
+    fn reserved_words_are_completed_unquoted() {
+        let mut shell = Shell::for_tests();
+        shell.variables.set("PATH", "");
+        let completion = complete_line(&mut shell, "{");
+        assert_eq!(completion.insertion().as_deref(), Some("{ "));
+        let completion = complete_line(&mut shell, "true; !");
+        assert_eq!(completion.insertion().as_deref(), Some("! "));
+        let completion = complete_line(&mut shell, "wh");
+        assert_eq!(completion.insertion().as_deref(), Some("while "));
+    }
+
+    #[test]
+    fn several_candidates_insert_their_longest_common_prefix() {
+        let completion = |replacements: &[&str]| Completion {
+            context: Context::Command,
+            range: 0..0,
+            candidates: replacements
+                .iter()
+                .map(|replacement| Candidate {
+                    display: replacement.to_string(),
+                    replacement: replacement.to_string(),
+                    suffix: String::from(" "),
+                })
+                .collect(),
+        };
+
+        assert_eq!(completion(&[]).insertion(), None);
+        assert_eq!(completion(&["make"]).insertion().as_deref(), Some("make "));
+        assert_eq!(
+            completion(&["make", "makefile", "mkdir"])
+                .insertion()
+                .as_deref(),
+            Some("m")
+        );
+        assert_eq!(
+            completion(&["makefile", "make"]).insertion().as_deref(),
+            Some("make")
+        );
+        assert_eq!(
+            completion(&["caf\u{e9}s", "caf\u{e8}"])
+                .insertion()
+                .as_deref(),
+            Some("caf")
+        );
+        assert_eq!(completion(&["a", "b"]).insertion().as_deref(), Some(""));
+    }
+}
@@ -6,6 +6,7 @@ use crate::args::Args;
 pub struct Config {
     pub home: PathBuf,
     pub init_file: PathBuf,
+    pub history_file: PathBuf,
 }
 
 impl Config {
@@ -30,6 +31,20 @@ impl Config {
             home.join("init.sh")
         };
 
-        Self { home, init_file }
+        // `HISTFILE` overrides this once the init file has run.
+        let history_file = if let Some(xdg_state_home) = env::var_os("XDG_STATE_HOME") {
+            PathBuf::from(xdg_state_home).join("oxide-film/history")
+        } else if cfg!(target_os = "macos") {
+            PathBuf::from(env::var_os("HOME").unwrap())
+                .join("Library/Application Support/oxide-film/history")
+        } else {
+            PathBuf::from(env::var_os("HOME").unwrap()).join(".local/state/oxide-film/history")
+        };
+
+        Self {
+            home,
+            init_file,
+            history_file,
+        }
     }
 }
@@ -1,32 +1,22 @@
-use std::io::Read;
+use std::{
+    collections::VecDeque,
+    io::{Read, Write},
+    ops::BitOr,
+    os::fd::{AsFd, AsRawFd, RawFd},
+    time::Duration,
+};
 
-#[derive(Debug, PartialEq)]
-pub enum Key<'a> {
+use crate::base64;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Key {
     Esc,
     Backspace,
     F(u8),
-    Alt(std::borrow::Cow<'a, str>),
-    Ctrl(std::borrow::Cow<'a, str>),
-    Character(std::borrow::Cow<'a, str>),
+    /// A character, which is the lowercase letter of a control character typed with Ctrl.
+    Character(char),
     Null,
 
-    ShiftLeft,
-    ShiftRight,
-    ShiftUp,
-    ShiftDown,
-
-    AltLeft,
-    AltRight,
-    AltUp,
-    AltDown,
-
-    CtrlLeft,
-    CtrlRight,
-    CtrlUp,
-    CtrlDown,
-    CtrlHome,
-    CtrlEnd,
-
     Left,
     Right,
     Up,
@@ -44,14 +34,93 @@ pub enum Key<'a> {
     Delete,
 }
 
+/// The modifier keys held with a key.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Modifiers(u8);
+
+impl Modifiers {
+    pub const NONE: Self = Self(0);
+    pub const SHIFT: Self = Self(1);
+    pub const ALT: Self = Self(2);
+    pub const CTRL: Self = Self(4);
+    pub const SUPER: Self = Self(8);
+    pub const HYPER: Self = Self(16);
+    pub const META: Self = Self(32);
+
+    /// Decodes the modifier parameter of an xterm key sequence, which is 1 plus a bitmask of
+    /// Shift, Alt, Ctrl and Meta.
+    pub fn from_xterm(parameter: u16) -> Self {
+        let mask = parameter.saturating_sub(1);
+        [Self::SHIFT, Self::ALT, Self::CTRL, Self::META]
+            .into_iter()
+            .enumerate()
+            .filter(|(bit, _)| mask & (1 << bit) != 0)
+            .fold(Self::NONE, |modifiers, (_, modifier)| modifiers | modifier)
+    }
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Decodes the modifier parameter of the kitty keyboard protocol, which is 1 plus a
+    /// bitmask in the order of the constants above, followed by the lock keys.
+    pub fn from_kitty(parameter: u32) -> Self {
+        Self(parameter.saturating_sub(1) as u8 & 0b11_1111)
+    }
+
+    /// Returns the modifiers that are not in `other`.
+    pub fn without(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+}
+
+impl BitOr for Modifiers {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+/// Whether a key was pressed, held down or released. Terminals without the kitty keyboard
+/// protocol only report presses.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum KeyKind {
+    #[default]
+    Press,
+    Repeat,
+    Release,
+}
+
+impl KeyKind {
+    fn from_kitty(parameter: u32) -> Option<Self> {
+        match parameter {
+            1 => Some(Self::Press),
+            2 => Some(Self::Repeat),
+            3 => Some(Self::Release),
+            _ => None,
+        }
+    }
+}
+
+/// A mouse event at a column and row counted from 1, or at a position in pixels when the
+/// terminal reports pixels.
 #[derive(Debug, PartialEq)]
 pub enum Mouse {
     Press(MouseButton, u16, u16),
-    Release(u16, u16),
-    Hold(u16, u16),
+    /// The release of a button, which only SGR reports tell.
+    Release(Option<MouseButton>, u16, u16),
+    /// Motion with a button held down.
+    Drag(MouseButton, u16, u16),
+    /// Motion with no button held down.
+    Move(u16, u16),
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MouseButton {
     Left,
     Right,
@@ -60,346 +129,1376 @@ pub enum MouseButton {
     WheelDown,
     WheelLeft,
     WheelRight,
+    Back,
+    Forward,
+}
+
+impl Mouse {
+    /// Decodes the button code of a mouse report, which also has bits for the modifiers and
+    /// for motion. A release of a button has the code of no button, unless `release` tells it.
+    fn decode(cb: u16, x: u16, y: u16, release: bool) -> Option<(Self, Modifiers)> {
+        let modifiers = [
+            (4, Modifiers::SHIFT),
+            (8, Modifiers::ALT),
+            (16, Modifiers::CTRL),
+        ]
+        .into_iter()
+        .filter(|(bit, _)| cb & bit != 0)
+        .fold(Modifiers::NONE, |modifiers, (_, modifier)| {
+            modifiers | modifier
+        });
+        let button = match cb & !0b11_1100 {
+            0 => Some(MouseButton::Left),
+            1 => Some(MouseButton::Middle),
+            2 => Some(MouseButton::Right),
+            3 => None,
+            64 => Some(MouseButton::WheelUp),
+            65 => Some(MouseButton::WheelDown),
+            66 => Some(MouseButton::WheelLeft),
+            67 => Some(MouseButton::WheelRight),
+            128 => Some(MouseButton::Back),
+            129 => Some(MouseButton::Forward),
+            _ => return None,
+        };
+        let motion = cb & 32 != 0;
+        let event = match (button, motion) {
+            (Some(button), true) => Self::Drag(button, x, y),
+            (None, true) => Self::Move(x, y),
+            (button, false) if release => Self::Release(button, x, y),
+            (Some(button), false) => Self::Press(button, x, y),
+            (None, false) => Self::Release(None, x, y),
+        };
+        Some((event, modifiers))
+    }
+}
+
+/// The mouse events a terminal can be asked to report, which it encodes as SGR reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseTracking {
+    /// Presses and releases of buttons, including the wheel.
+    Clicks,
+    /// Clicks, and motion while a button is held down.
+    Drags,
+    /// Clicks, and all motion.
+    Motion,
+}
+
+impl MouseTracking {
+    /// Returns the sequence asking the terminal for the reports, with positions in pixels
+    /// rather than cells if `pixels` is set.
+    pub fn enable(self, pixels: bool) -> String {
+        let mode = match self {
+            Self::Clicks => 1000,
+            Self::Drags => 1002,
+            Self::Motion => 1003,
+        };
+        let encoding = if pixels { 1016 } else { 1006 };
+        format!("\x1b[?{mode}h\x1b[?{encoding}h")
+    }
+
+    /// Returns the sequence stopping any of the reports.
+    pub fn disable() -> &'static str {
+        "\x1b[?1003l\x1b[?1002l\x1b[?1000l\x1b[?1016l\x1b[?1006l"
+    }
 }
 
+/// A reply of the terminal to a query sent to it.
 #[derive(Debug, PartialEq)]
-pub enum Event<'a> {
-    Key(Key<'a>),
-    Mouse(Mouse),
+pub enum Response {
+    /// The row and column of the cursor, counted from 1, asked for with `ESC [ 6 n`.
+    CursorPosition(u16, u16),
+    /// The conformance level and the features of the terminal, asked for with `ESC [ c`.
+    PrimaryAttributes(Vec<u16>),
+    /// The type, firmware version and ROM cartridge of the terminal, asked for with
+    /// `ESC [ > c`.
+    SecondaryAttributes(Vec<u16>),
+    /// The name and version of the terminal, asked for with `ESC [ > q`.
+    Version(String),
+    /// The enhancements of the kitty keyboard protocol in effect, asked for with `ESC [ ? u`.
+    KeyboardFlags(u8),
+    /// A color of the terminal with 16 bits per channel, asked for with `OSC 10 ; ?` and the
+    /// like.
+    Color(TerminalColor, [u16; 3]),
+}
+
+/// The colors a terminal reports with OSC 4, 10, 11 and 12.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TerminalColor {
+    Palette(u8),
+    Foreground,
+    Background,
+    Cursor,
+}
+
+/// Parses a color specification of the form `rgb:r/g/b`, with one to four hex digits per
+/// channel, scaled to 16 bits.
+fn rgb(spec: &[u8]) -> Option<[u16; 3]> {
+    let spec = std::str::from_utf8(spec.strip_prefix(b"rgb:")?).ok()?;
+    let mut channels = spec.split('/').map(|channel| {
+        let value = u32::from_str_radix(channel, 16).ok()?;
+        let max = (1 << (4 * channel.len() as u32).clamp(4, 16)) - 1;
+        (channel.len() <= 4).then(|| (value * 0xffff / max) as u16)
+    });
+    let rgb = [channels.next()??, channels.next()??, channels.next()??];
+    channels.next().is_none().then_some(rgb)
+}
+
+/// The system clipboard, reached through the terminal with OSC 52 so that it works over SSH.
+pub struct Clipboard;
+
+impl Clipboard {
+    /// The sequence asking the terminal for the contents of the clipboard, which not every
+    /// terminal allows.
+    pub const REQUEST: &str = "\x1b]52;c;?\x1b\\";
+
+    /// Returns the sequence putting `text` in the clipboard.
+    pub fn copy(text: &str) -> String {
+        format!("\x1b]52;c;{}\x1b\\", base64::encode(text.as_bytes()))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Event {
+    Key(Key, Modifiers, KeyKind),
+    Mouse(Mouse, Modifiers),
+    /// Text pasted while bracketed paste mode is enabled, to be inserted as is.
+    Paste(String),
+    /// The contents of the clipboard, sent by the terminal when asked with
+    /// `Clipboard::REQUEST`.
+    Clipboard(String),
+    /// The terminal window got or lost the focus, reported after `ESC [ ? 1004 h`.
+    FocusGained,
+    FocusLost,
+    Response(Response),
     Unknown(Vec<u8>),
 }
 
-impl<'a> Event<'a> {
-    fn parse_csi(bytes: &mut impl Iterator<Item = std::io::Result<u8>>) -> std::io::Result<Self> {
-        Ok(match bytes.next() {
-            Some(Ok(b'[')) => match bytes.next() {
-                Some(Ok(b @ b'A'..=b'E')) => Self::Key(Key::F(b - b'A' + 1)),
-                _ => todo!(),
-            },
-            Some(Ok(b'1')) => {
-                let _semicolon = bytes.next();
-                match bytes.next() {
-                    Some(Ok(b'2')) => match bytes.next() {
-                        Some(Ok(b'A')) => Self::Key(Key::ShiftUp),
-                        Some(Ok(b'B')) => Self::Key(Key::ShiftDown),
-                        Some(Ok(b'C')) => Self::Key(Key::ShiftRight),
-                        Some(Ok(b'D')) => Self::Key(Key::ShiftLeft),
-                        _ => todo!(),
-                    },
-                    Some(Ok(b'3')) => match bytes.next() {
-                        Some(Ok(b'A')) => Self::Key(Key::AltUp),
-                        Some(Ok(b'B')) => Self::Key(Key::AltDown),
-                        Some(Ok(b'C')) => Self::Key(Key::AltRight),
-                        Some(Ok(b'D')) => Self::Key(Key::AltLeft),
-                        _ => todo!(),
-                    },
-                    Some(Ok(b'5')) => match bytes.next() {
-                        Some(Ok(b'A')) => Self::Key(Key::CtrlUp),
-                        Some(Ok(b'B')) => Self::Key(Key::CtrlDown),
-                        Some(Ok(b'C')) => Self::Key(Key::CtrlRight),
-                        Some(Ok(b'D')) => Self::Key(Key::CtrlLeft),
-                        Some(Ok(b'F')) => Self::Key(Key::CtrlEnd),
-                        Some(Ok(b'H')) => Self::Key(Key::CtrlHome),
-                        _ => todo!(),
-                    },
-                    _ => todo!(),
-                }
-            }
-            Some(Ok(b'A')) => Self::Key(Key::Up),
-            Some(Ok(b'B')) => Self::Key(Key::Down),
-            Some(Ok(b'C')) => Self::Key(Key::Right),
-            Some(Ok(b'D')) => Self::Key(Key::Left),
-            Some(Ok(b'F')) => Self::Key(Key::End),
-            Some(Ok(b'H')) => Self::Key(Key::Home),
-            Some(Ok(b'Z')) => Self::Key(Key::BackTab),
-            Some(Ok(b'M')) => {
-                // X10 emulation mouse encoding: ESC [ CB Cx Cy (6 characters only).
-                let mut next = || bytes.next().unwrap().unwrap();
-
-                let cb = next() as i8 - 32;
-                // (1, 1) are the coords for upper left.
-                let cx = next().saturating_sub(32) as u16;
-                let cy = next().saturating_sub(32) as u16;
-                Self::Mouse(match cb & 0b11 {
-                    0 => {
-                        if cb & 0x40 != 0 {
-                            Mouse::Press(MouseButton::WheelUp, cx, cy)
-                        } else {
-                            Mouse::Press(MouseButton::Left, cx, cy)
-                        }
-                    }
-                    1 => {
-                        if cb & 0x40 != 0 {
-                            Mouse::Press(MouseButton::WheelDown, cx, cy)
-                        } else {
-                            Mouse::Press(MouseButton::Middle, cx, cy)
-                        }
-                    }
-                    2 => {
-                        if cb & 0x40 != 0 {
-                            Mouse::Press(MouseButton::WheelLeft, cx, cy)
-                        } else {
-                            Mouse::Press(MouseButton::Right, cx, cy)
-                        }
-                    }
-                    3 => {
-                        if cb & 0x40 != 0 {
-                            Mouse::Press(MouseButton::WheelRight, cx, cy)
-                        } else {
-                            Mouse::Release(cx, cy)
-                        }
-                    }
-                    _ => todo!(),
+/// The bytes of an input stream, of which the next one can be looked at before it is consumed,
+/// so that a malformed sequence ends before the byte starting the next one.
+pub struct Bytes<I> {
+    bytes: I,
+    peeked: Option<u8>,
+    /// The bytes consumed by the event being parsed.
+    consumed: Vec<u8>,
+}
+
+impl<I: Iterator<Item = std::io::Result<u8>>> Bytes<I> {
+    pub fn new(bytes: I) -> Self {
+        Self {
+            bytes,
+            peeked: None,
+            consumed: Vec::new(),
+        }
+    }
+
+    /// Returns the byte that was looked at but not consumed.
+    pub fn peeked(&self) -> Option<u8> {
+        self.peeked
+    }
+
+    fn next(&mut self) -> std::io::Result<Option<u8>> {
+        let byte = match self.peeked.take() {
+            Some(byte) => Some(byte),
+            None => self.bytes.next().transpose()?,
+        };
+        self.consumed.extend(byte);
+        Ok(byte)
+    }
+
+    /// Returns whether there are no more bytes.
+    fn ended(&mut self) -> std::io::Result<bool> {
+        Ok(self.next_if(|_| false)?.is_none() && self.peeked.is_none())
+    }
+
+    /// Consumes the next byte if it satisfies `accept`.
+    fn next_if(&mut self, accept: impl FnOnce(u8) -> bool) -> std::io::Result<Option<u8>> {
+        if self.peeked.is_none() {
+            self.peeked = self.bytes.next().transpose()?;
+        }
+        match self.peeked {
+            Some(byte) if accept(byte) => self.next(),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Parses the numeric parameters of a CSI sequence, an empty one meaning 1.
+fn numbers(params: &[u8]) -> Option<Vec<u16>> {
+    fields(params)?
+        .into_iter()
+        .map(|field| match field.as_slice() {
+            [number] => number.unwrap_or(1).try_into().ok(),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Parses the parameters of a CSI sequence as fields of colon-separated sub-parameters, an
+/// empty one being `None`.
+fn fields(params: &[u8]) -> Option<Vec<Vec<Option<u32>>>> {
+    std::str::from_utf8(params)
+        .ok()?
+        .split(';')
+        .map(|field| {
+            field
+                .split(':')
+                .map(|n| match n {
+                    "" => Some(None),
+                    n => n.parse().ok().map(Some),
                 })
+                .collect()
+        })
+        .collect()
+}
+
+/// Returns the key of a kitty keyboard protocol key code, which is a Unicode code point or one
+/// of the functional keys given a code in the private use area.
+fn kitty_key(code: u32) -> Option<Key> {
+    let character = Key::Character;
+    Some(match code {
+        9 => character('\t'),
+        13 | 57414 => character('\n'),
+        27 => Key::Esc,
+        127 => Key::Backspace,
+        // F13-F35
+        n @ 57376..=57398 => Key::F((n - 57376 + 13) as u8),
+        // The keypad.
+        n @ 57399..=57408 => character(char::from_digit(n - 57399, 10)?),
+        57409 => character('.'),
+        57410 => character('/'),
+        57411 => character('*'),
+        57412 => character('-'),
+        57413 => character('+'),
+        57415 => character('='),
+        57416 => character(','),
+        57417 => Key::Left,
+        57418 => Key::Right,
+        57419 => Key::Up,
+        57420 => Key::Down,
+        57421 => Key::PageUp,
+        57422 => Key::PageDown,
+        57423 => Key::Home,
+        57424 => Key::End,
+        57425 => Key::Insert,
+        57426 => Key::Delete,
+        // Lock, media and modifier keys are not used by the editor.
+        57344..=63743 => return None,
+        n => character(char::from_u32(n)?),
+    })
+}
+
+impl Event {
+    /// Parses the sequence following `ESC [`, or returns `None` if it is malformed or unknown.
+    fn parse_csi<I: Iterator<Item = std::io::Result<u8>>>(
+        bytes: &mut Bytes<I>,
+    ) -> std::io::Result<Option<Self>> {
+        // Function keys of the Linux console: ESC [ [ A-E.
+        if bytes.next_if(|b| b == b'[')?.is_some() {
+            return Ok(bytes
+                .next_if(|b| (b'A'..=b'E').contains(&b))?
+                .map(|b| Self::Key(Key::F(b - b'A' + 1), Modifiers::NONE, KeyKind::Press)));
+        }
+        // X10 emulation mouse encoding: ESC [ M Cb Cx Cy, with each value offset by 32.
+        if bytes.next_if(|b| b == b'M')?.is_some() {
+            let (Some(cb), Some(cx), Some(cy)) = (bytes.next()?, bytes.next()?, bytes.next()?)
+            else {
+                return Ok(None);
+            };
+            let [cb, cx, cy] = [cb, cx, cy].map(|b| u16::from(b).saturating_sub(32));
+            return Ok(Mouse::decode(cb, cx, cy, false)
+                .map(|(mouse, modifiers)| Self::Mouse(mouse, modifiers)));
+        }
+
+        // Parameter bytes, intermediate bytes, then the final byte.
+        let mut params = Vec::new();
+        while let Some(b) = bytes.next_if(|b| (0x30..=0x3f).contains(&b))? {
+            params.push(b);
+        }
+        while bytes.next_if(|b| (0x20..=0x2f).contains(&b))?.is_some() {}
+        let Some(c) = bytes.next_if(|b| (0x40..=0x7e).contains(&b))? else {
+            return Ok(None);
+        };
+
+        if let Some(params) = params.strip_prefix(b"<") {
+            return Ok(Self::parse_sgr_mouse(params, c));
+        }
+        // Replies to queries: ESC [ ? parameters c, ESC [ ? flags u and ESC [ > parameters c
+        if let [prefix @ (b'?' | b'>'), params @ ..] = params.as_slice() {
+            let response = match (prefix, c, numbers(params)) {
+                (b'?', b'c', Some(numbers)) => Response::PrimaryAttributes(numbers),
+                (b'>', b'c', Some(numbers)) => Response::SecondaryAttributes(numbers),
+                (b'?', b'u', Some(numbers)) => match numbers.as_slice() {
+                    &[flags] => Response::KeyboardFlags(flags as u8),
+                    _ => return Ok(None),
+                },
+                _ => return Ok(None),
+            };
+            return Ok(Some(Self::Response(response)));
+        }
+        if params
+            .first()
+            .is_some_and(|b| !b.is_ascii_digit() && *b != b';' && *b != b':')
+        {
+            return Ok(None);
+        }
+        // Bracketed paste: ESC [ 200 ~ text ESC [ 201 ~
+        if c == b'~' && params == b"200" {
+            return Self::parse_paste(bytes).map(Some);
+        }
+        let Some(fields) = fields(&params) else {
+            return Ok(None);
+        };
+        if c == b'u' {
+            return Ok(Self::parse_kitty_key(&fields));
+        }
+        // The kitty keyboard protocol adds the kind of the event to the modifiers of the
+        // legacy sequences it keeps: ESC [ 1 ; modifiers : kind A
+        let kind = match fields.get(1).and_then(|field| field.get(1)) {
+            Some(&kind) => match KeyKind::from_kitty(kind.unwrap_or(1)) {
+                Some(kind) => kind,
+                None => return Ok(None),
+            },
+            None => KeyKind::Press,
+        };
+        let Some(nums) = fields
+            .iter()
+            .enumerate()
+            .map(|(i, field)| match field.as_slice() {
+                [number] => number.unwrap_or(1).try_into().ok(),
+                [number, _] if i == 1 => number.unwrap_or(1).try_into().ok(),
+                _ => None,
+            })
+            .collect::<Option<Vec<u16>>>()
+        else {
+            return Ok(None);
+        };
+
+        Ok(match (nums.as_slice(), c) {
+            ([] | [1], b'I') => Some(Self::FocusGained),
+            ([] | [1], b'O') => Some(Self::FocusLost),
+            // A cursor position report, which takes the place of F3 with modifiers:
+            // ESC [ row ; column R
+            (&[row, column], b'R') => Some(Self::Response(Response::CursorPosition(row, column))),
+            // rxvt mouse encoding:
+            // ESC [ Cb ; Cx ; Cy ; M
+            (&[cb, cx, cy], b'M') => Mouse::decode(cb.wrapping_sub(32), cx, cy, false)
+                .map(|(mouse, modifiers)| Self::Mouse(mouse, modifiers)),
+            // A character with modifiers, as sent when xterm's modifyOtherKeys is set:
+            // ESC [ 27 ; modifiers ; code ~
+            (&[27, modifiers, code], b'~') => char::from_u32(code.into())
+                .map(|c| Self::Key(Key::Character(c), Modifiers::from_xterm(modifiers), kind)),
+            // Special key code, with the modifiers as an optional second parameter:
+            // ESC [ code ; modifiers ~
+            (&[code, ref modifiers @ ..], b'~') if modifiers.len() <= 1 => {
+                let key = match code {
+                    0x01 | 0x07 => Key::Home,
+                    0x02 => Key::Insert,
+                    0x03 => Key::Delete,
+                    0x04 | 0x08 => Key::End,
+                    0x05 => Key::PageUp,
+                    0x06 => Key::PageDown,
+                    n @ 0x0b..=0x0f => Key::F(n as u8 - 0x0a),
+                    n @ 0x11..=0x15 => Key::F(n as u8 - 0x0b),
+                    n @ 0x17..=0x18 => Key::F(n as u8 - 0x0c),
+                    _ => return Ok(None),
+                };
+                let modifiers = modifiers.first().copied().unwrap_or(1);
+                Some(Self::Key(key, Modifiers::from_xterm(modifiers), kind))
             }
-            Some(Ok(b'<')) => {
-                // xterm mouse encoding:
-                // ESC [ < Cb ; Cx ; Cy (;) (M or m)
-                let mut buf = Vec::new();
-                let mut c = bytes.next().unwrap().unwrap();
-                while match c {
-                    b'm' | b'M' => false,
-                    _ => true,
-                } {
-                    buf.push(c);
-                    c = bytes.next().unwrap().unwrap();
-                }
-                let str_buf = String::from_utf8(buf).unwrap();
-                let nums = &mut str_buf.split(';');
-
-                let cb = nums.next().unwrap().parse::<u16>().unwrap();
-                let cx = nums.next().unwrap().parse::<u16>().unwrap();
-                let cy = nums.next().unwrap().parse::<u16>().unwrap();
-
-                let event = match cb {
-                    0..=2 | 64..=67 => {
-                        let button = match cb {
-                            0 => MouseButton::Left,
-                            1 => MouseButton::Middle,
-                            2 => MouseButton::Right,
-                            64 => MouseButton::WheelUp,
-                            65 => MouseButton::WheelDown,
-                            66 => MouseButton::WheelLeft,
-                            67 => MouseButton::WheelRight,
-                            _ => unreachable!(),
-                        };
-                        match c {
-                            b'M' => Mouse::Press(button, cx, cy),
-                            b'm' => Mouse::Release(cx, cy),
-                            _ => todo!(),
-                        }
-                    }
-                    32 => Mouse::Hold(cx, cy),
-                    3 => Mouse::Release(cx, cy),
-                    _ => todo!(),
+            // Cursor and function keys, with the modifiers as the second parameter:
+            // ESC [ 1 ; modifiers final
+            ([] | [1] | [1, _], _) => {
+                let key = match c {
+                    b'A' => Key::Up,
+                    b'B' => Key::Down,
+                    b'C' => Key::Right,
+                    b'D' => Key::Left,
+                    b'F' => Key::End,
+                    b'H' => Key::Home,
+                    b'P'..=b'S' => Key::F(c - b'P' + 1),
+                    b'Z' => Key::BackTab,
+                    _ => return Ok(None),
                 };
-
-                Self::Mouse(event)
+                let modifiers = nums.get(1).copied().unwrap_or(1);
+                Some(Self::Key(key, Modifiers::from_xterm(modifiers), kind))
             }
-            Some(Ok(c @ b'0'..=b'9')) => {
-                // Numbered escape code.
-                let mut buf = Vec::new();
-                buf.push(c);
-                let mut c = bytes.next().unwrap().unwrap();
-                // The final byte of a CSI sequence can be in the range 64-126, so
-                // let's keep reading anything else.
-                while !(64..=126).contains(&c) {
-                    buf.push(c);
-                    c = bytes.next().unwrap().unwrap();
-                }
+            _ => None,
+        })
+    }
 
-                match c {
-                    // rxvt mouse encoding:
-                    // ESC [ Cb ; Cx ; Cy ; M
-                    b'M' => {
-                        let str_buf = String::from_utf8(buf).unwrap();
-
-                        let nums: Vec<u16> =
-                            str_buf.split(';').map(|n| n.parse().unwrap()).collect();
-
-                        let cb = nums[0];
-                        let cx = nums[1];
-                        let cy = nums[2];
-
-                        let event = match cb {
-                            32 => Mouse::Press(MouseButton::Left, cx, cy),
-                            33 => Mouse::Press(MouseButton::Middle, cx, cy),
-                            34 => Mouse::Press(MouseButton::Right, cx, cy),
-                            35 => Mouse::Release(cx, cy),
-                            64 => Mouse::Hold(cx, cy),
-                            96 | 97 => Mouse::Press(MouseButton::WheelUp, cx, cy),
-                            _ => todo!(),
-                        };
-
-                        Self::Mouse(event)
-                    }
-                    // Special key code.
-                    b'~' => {
-                        let str_buf = String::from_utf8(buf).unwrap();
-
-                        // This CSI sequence can be a list of semicolon-separated
-                        // numbers.
-                        let nums: Vec<u8> =
-                            str_buf.split(';').map(|n| n.parse().unwrap()).collect();
-
-                        if nums.is_empty() {
-                            todo!();
-                        }
-
-                        // TODO: handle multiple values for key modifiers (ex: values [3, 2] means Shift+Delete)
-                        if nums.len() > 1 {
-                            todo!();
-                        }
-
-                        match nums[0] {
-                            0x01 | 0x07 => Self::Key(Key::Home),
-                            0x02 => Self::Key(Key::Insert),
-                            0x03 => Self::Key(Key::Delete),
-                            0x04 | 0x08 => Self::Key(Key::End),
-                            0x05 => Self::Key(Key::PageUp),
-                            0x06 => Self::Key(Key::PageDown),
-                            b @ 0x0b..=0x0f => Self::Key(Key::F(b - 0x0a)),
-                            b @ 0x11..=0x15 => Self::Key(Key::F(b - 0x0b)),
-                            b @ 0x17..=0x18 => Self::Key(Key::F(b - 0x0c)),
-                            b => todo!(),
-                        }
-                    }
-                    _ => todo!(),
-                }
+    /// Parses the fields of a key in the kitty keyboard protocol:
+    /// ESC [ code : shifted : base ; modifiers : kind ; text u
+    ///
+    /// A key typed with Shift is the shifted key when the terminal reports it, and a shortcut
+    /// with other modifiers is the key in the same place on a US keyboard, so that it does not
+    /// depend on the keyboard layout.
+    fn parse_kitty_key(fields: &[Vec<Option<u32>>]) -> Option<Self> {
+        let [codes, rest @ ..] = fields else {
+            return None;
+        };
+        if rest.len() > 2 || codes.len() > 3 {
+            return None;
+        }
+        let (modifiers, kind) = match rest.first().map(Vec::as_slice) {
+            None | Some([]) => (1, 1),
+            Some(&[modifiers]) => (modifiers.unwrap_or(1), 1),
+            Some(&[modifiers, kind]) => (modifiers.unwrap_or(1), kind.unwrap_or(1)),
+            Some(_) => return None,
+        };
+        let modifiers = Modifiers::from_kitty(modifiers);
+        let kind = KeyKind::from_kitty(kind)?;
+
+        let code = |i: usize| codes.get(i).copied().flatten();
+        let code = if !modifiers.without(Modifiers::SHIFT).is_empty() && code(2).is_some() {
+            code(2)
+        } else if modifiers.contains(Modifiers::SHIFT) && code(1).is_some() {
+            code(1)
+        } else {
+            code(0)
+        }?;
+        Some(Self::Key(kitty_key(code)?, modifiers, kind))
+    }
+
+    /// Parses the parameters of an xterm mouse report after `ESC [ <`, with `c` being `M` for
+    /// a press and `m` for a release. SGR-pixels reports have the same form.
+    /// ESC [ < Cb ; Cx ; Cy (M or m)
+    fn parse_sgr_mouse(params: &[u8], c: u8) -> Option<Self> {
+        let &[cb, cx, cy] = numbers(params)?.as_slice() else {
+            return None;
+        };
+        let release = match c {
+            b'M' => false,
+            b'm' => true,
+            _ => return None,
+        };
+        let (mouse, modifiers) = Mouse::decode(cb, cx, cy, release)?;
+        Some(Self::Mouse(mouse, modifiers))
+    }
+
+    /// Reads the text of a bracketed paste, up to its end sequence.
+    fn parse_paste<I: Iterator<Item = std::io::Result<u8>>>(
+        bytes: &mut Bytes<I>,
+    ) -> std::io::Result<Self> {
+        const END: &[u8] = b"\x1b[201~";
+
+        let mut buffer = Vec::new();
+        while !buffer.ends_with(END) {
+            match bytes.next()? {
+                Some(byte) => buffer.push(byte),
+                None => break,
             }
-            _ => todo!(),
+        }
+        if buffer.ends_with(END) {
+            buffer.truncate(buffer.len() - END.len());
+        }
+
+        // Terminals send the newlines of the pasted text as carriage returns.
+        let text = String::from_utf8_lossy(&buffer)
+            .replace("\r\n", "\n")
+            .replace('\r', "\n");
+        Ok(Self::Paste(text))
+    }
+
+    /// Parses the operating system command following `ESC ]`, up to the BEL or `ESC \\` ending
+    /// it, or returns `None` if it is malformed or unknown.
+    fn parse_osc<I: Iterator<Item = std::io::Result<u8>>>(
+        bytes: &mut Bytes<I>,
+    ) -> std::io::Result<Option<Self>> {
+        let Some(command) = Self::parse_string(bytes)? else {
+            return Ok(None);
+        };
+
+        let fields: Vec<&[u8]> = command.split(|&b| b == b';').collect();
+        let color = |color, spec| Some(Self::Response(Response::Color(color, rgb(spec)?)));
+        Ok(match fields.as_slice() {
+            [b"52", _, data] => base64::decode(data)
+                .map(|data| Self::Clipboard(String::from_utf8_lossy(&data).into_owned())),
+            [b"4", index, spec] => std::str::from_utf8(index)
+                .ok()
+                .and_then(|index| index.parse().ok())
+                .and_then(|index| color(TerminalColor::Palette(index), spec)),
+            [b"10", spec] => color(TerminalColor::Foreground, spec),
+            [b"11", spec] => color(TerminalColor::Background, spec),
+            [b"12", spec] => color(TerminalColor::Cursor, spec),
+            _ => None,
         })
     }
 
-    fn parse_utf8(
+    /// Parses the device control string following `ESC P >`, of which only the reply to
+    /// XTVERSION is known: ESC P > | text ESC \\
+    fn parse_dcs<I: Iterator<Item = std::io::Result<u8>>>(
+        bytes: &mut Bytes<I>,
+    ) -> std::io::Result<Option<Self>> {
+        let Some(string) = Self::parse_string(bytes)? else {
+            return Ok(None);
+        };
+        Ok(string.strip_prefix(b"|").map(|version| {
+            Self::Response(Response::Version(
+                String::from_utf8_lossy(version).into_owned(),
+            ))
+        }))
+    }
+
+    /// Reads a control string up to the BEL or `ESC \\` ending it, or returns `None` if it has
+    /// a control character in it.
+    fn parse_string<I: Iterator<Item = std::io::Result<u8>>>(
+        bytes: &mut Bytes<I>,
+    ) -> std::io::Result<Option<Vec<u8>>> {
+        let mut string = Vec::new();
+        loop {
+            match bytes.next()? {
+                Some(0x07) => return Ok(Some(string)),
+                Some(0x1b) if bytes.next_if(|b| b == b'\\')?.is_some() => return Ok(Some(string)),
+                Some(byte) if byte >= 0x20 => string.push(byte),
+                _ => return Ok(None),
+            }
+        }
+    }
+
+    /// Parses the character starting with `byte`, or returns `None` if it is not valid UTF-8.
+    fn parse_utf8<I: Iterator<Item = std::io::Result<u8>>>(
         byte: u8,
-        rest_bytes: &mut impl Iterator<Item = std::io::Result<u8>>,
-    ) -> std::io::Result<std::borrow::Cow<'a, str>> {
+        rest_bytes: &mut Bytes<I>,
+    ) -> std::io::Result<Option<char>> {
         if byte.is_ascii() {
-            let bytes = &[byte];
-            return Ok(std::borrow::Cow::Borrowed(Box::leak(
-                std::str::from_utf8(bytes).unwrap().into(),
-            )));
+            return Ok(Some(byte.into()));
         }
 
-        let mut buffer = Vec::from([byte]);
-        loop {
-            match rest_bytes.next() {
-                Some(Ok(next)) => {
-                    buffer.push(next);
-                    if let Ok(str) = std::str::from_utf8(&buffer) {
-                        return Ok(std::borrow::Cow::Owned(str.into()));
-                    }
-                    if buffer.len() >= 4 {
-                        return Err(std::io::Error::other(format!(
-                            "Invalid UTF-8 sequence: {:?}",
-                            buffer
-                        )));
-                    }
-                }
-                b => {
-                    return Err(std::io::Error::other(format!(
-                        "Invalid UTF-8 sequence: {:?}",
-                        b
-                    )))
-                }
+        let len = match byte {
+            0xc2..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf4 => 4,
+            _ => return Ok(None),
+        };
+        let mut buffer = [byte, 0, 0, 0];
+        for byte in &mut buffer[1..len] {
+            match rest_bytes.next_if(|b| (0x80..=0xbf).contains(&b))? {
+                Some(next) => *byte = next,
+                None => return Ok(None),
             }
         }
+        Ok(std::str::from_utf8(&buffer[..len])
+            .ok()
+            .and_then(|c| c.chars().next()))
     }
 
-    fn parse_escape_sequence(
-        bytes: &mut impl Iterator<Item = std::io::Result<u8>>,
-    ) -> std::io::Result<Self> {
-        match bytes.next() {
-            Some(Ok(b'O')) => {
-                match bytes.next() {
+    fn parse_escape_sequence<I: Iterator<Item = std::io::Result<u8>>>(
+        bytes: &mut Bytes<I>,
+    ) -> std::io::Result<Option<Self>> {
+        match bytes.next()? {
+            // An escape on its own is the Esc key, and one followed by the start of a sequence
+            // and nothing else is Alt with that key.
+            None => Ok(Some(Self::Key(Key::Esc, Modifiers::NONE, KeyKind::Press))),
+            Some(b @ (b'O' | b'[' | b']')) if bytes.ended()? => Ok(Some(Self::Key(
+                Key::Character(b.into()),
+                Modifiers::ALT,
+                KeyKind::Press,
+            ))),
+            Some(b'O') => Ok(bytes.next_if(|b| b"PQRSABCDFH".contains(&b))?.map(|b| {
+                let key = match b {
                     // F1-F4
-                    Some(Ok(b @ b'P'..=b'S')) => Ok(Event::Key(Key::F(b - b'P' + 1))),
-                    b => Err(std::io::Error::other(format!(
-                        "Invalid escape sequence: {}, {}, {:?}",
-                        0x1b, b'O', b
-                    ))),
-                }
-            }
-            Some(Ok(b'[')) => Self::parse_csi(bytes),
-            Some(Ok(b)) => Ok(Event::Key(Key::Alt(Self::parse_utf8(b, bytes)?))),
-            Some(Err(e)) => Err(e),
-            None => Err(std::io::Error::other("Cannot parse an event")),
+                    b'P'..=b'S' => Key::F(b - b'P' + 1),
+                    // Cursor keys in application mode.
+                    b'A' => Key::Up,
+                    b'B' => Key::Down,
+                    b'C' => Key::Right,
+                    b'D' => Key::Left,
+                    b'F' => Key::End,
+                    _ => Key::Home,
+                };
+                Self::Key(key, Modifiers::NONE, KeyKind::Press)
+            })),
+            Some(b'[') => Self::parse_csi(bytes),
+            Some(b']') => Self::parse_osc(bytes),
+            Some(b'P') if bytes.next_if(|b| b == b'>')?.is_some() => Self::parse_dcs(bytes),
+            // Alt sends the key prefixed with an escape.
+            Some(0x1b) => Ok(Some(Self::Key(Key::Esc, Modifiers::ALT, KeyKind::Press))),
+            Some(b) => Ok(Self::parse_key(b, bytes)?.map(|(key, modifiers)| {
+                Self::Key(key, modifiers | Modifiers::ALT, KeyKind::Press)
+            })),
         }
     }
 
-    fn parse(
+    /// Parses the key starting with `byte`, which is not an escape.
+    fn parse_key<I: Iterator<Item = std::io::Result<u8>>>(
         byte: u8,
-        rest_bytes: &mut impl Iterator<Item = std::io::Result<u8>>,
-    ) -> std::io::Result<Self> {
-        let mut buffer = Vec::from([byte]);
+        bytes: &mut Bytes<I>,
+    ) -> std::io::Result<Option<(Key, Modifiers)>> {
+        let control = |c: u8| Key::Character(c.into());
+        Ok(Some(match byte {
+            0x00 => (Key::Null, Modifiers::NONE),
+            b'\n' | b'\r' => (Key::Character('\n'), Modifiers::NONE),
+            b'\t' => (Key::Character('\t'), Modifiers::NONE),
+            b @ 0x01..=0x1a => (control(b - 0x01 + b'a'), Modifiers::CTRL),
+            b @ 0x1c..=0x1f => (control(b - 0x1c + b'4'), Modifiers::CTRL),
+            0x7f => (Key::Backspace, Modifiers::NONE),
+            b => match Self::parse_utf8(b, bytes)? {
+                Some(c) => (Key::Character(c), Modifiers::NONE),
+                None => return Ok(None),
+            },
+        }))
+    }
+
+    /// Parses the next event, or returns `None` at the end of the input. A malformed or
+    /// unknown sequence comes back as `Event::Unknown` with its bytes.
+    pub fn parse<I: Iterator<Item = std::io::Result<u8>>>(
+        bytes: &mut Bytes<I>,
+    ) -> std::io::Result<Option<Self>> {
+        bytes.consumed.clear();
+        let Some(byte) = bytes.next()? else {
+            return Ok(None);
+        };
+
+        let event = match byte {
+            0x1b => Self::parse_escape_sequence(bytes)?,
+            b => Self::parse_key(b, bytes)?
+                .map(|(key, modifiers)| Event::Key(key, modifiers, KeyKind::Press)),
+        };
+
+        Ok(Some(
+            event.unwrap_or_else(|| Event::Unknown(bytes.consumed.clone())),
+        ))
+    }
+}
+
+/// How long to wait for the rest of an escape sequence, unless `ESCDELAY` is set.
+pub const DEFAULT_ESCAPE_DELAY: Duration = Duration::from_millis(100);
+
+/// Parses events from bytes as they are fed to it, keeping an unfinished sequence until the
+/// rest of it arrives.
+#[derive(Debug, Default)]
+pub struct Decoder {
+    buffer: Vec<u8>,
+    /// How much of the buffer was searched for the end of the paste or control string it
+    /// starts with, so that the rest of a long one is not parsed again on every feed.
+    searched: usize,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Returns whether no bytes are left to parse.
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    /// Returns whether the buffer starts with the body of a bracketed paste or of a control
+    /// string, which is only over once its end sequence arrives.
+    pub fn in_string(&self) -> bool {
+        self.string_start().is_some()
+    }
+
+    /// Returns where the body of the paste or control string the buffer starts with begins.
+    fn string_start(&self) -> Option<usize> {
+        // An `ESC ]` with nothing after it is Alt-].
+        [&b"\x1b[200~"[..], b"\x1bP>", b"\x1b]"]
+            .into_iter()
+            .find(|start| {
+                self.buffer.starts_with(start) && (*start != b"\x1b]" || self.buffer.len() > 2)
+            })
+            .map(<[u8]>::len)
+    }
+
+    /// Returns the next event, taking an unfinished sequence as it is, such as an escape on
+    /// its own being the Esc key.
+    pub fn flush(&mut self) -> Option<Event> {
+        self.decode(true)
+    }
+
+    /// Returns whether the paste or control string the buffer starts with may have ended,
+    /// searching only the bytes fed since the last time.
+    fn string_ended(&mut self, start: usize) -> bool {
+        const PASTE_END: &[u8] = b"\x1b[201~";
+
+        // The search goes back over the start of an end sequence split across feeds.
+        let from = |overlap: usize| self.searched.saturating_sub(overlap).max(start);
+        let ended = match self.buffer.starts_with(b"\x1b[") {
+            true => self.buffer[from(PASTE_END.len() - 1)..]
+                .windows(PASTE_END.len())
+                .any(|window| window == PASTE_END),
+            // Any control character ends a control string, if only as a malformed one.
+            false => self.buffer[from(1)..].iter().any(|&b| b < 0x20),
+        };
+        self.searched = self.buffer.len();
+        ended
+    }
+
+    fn decode(&mut self, finished: bool) -> Option<Event> {
+        if !finished
+            && let Some(start) = self.string_start()
+            && !self.string_ended(start)
+        {
+            return None;
+        }
+
+        let mut exhausted = false;
+        let mut bytes = Bytes::new(self.buffer.iter().map(|&byte| Ok(byte)).chain(
+            std::iter::from_fn(|| {
+                exhausted = true;
+                None
+            }),
+        ));
+        let event = Event::parse(&mut bytes).ok().flatten()?;
+        let consumed = bytes.consumed.len();
+        if exhausted && !finished {
+            return None;
+        }
+        self.buffer.drain(..consumed);
+        self.searched = 0;
+        Some(event)
+    }
+}
+
+impl Iterator for Decoder {
+    type Item = Event;
+
+    /// Returns the next event, or `None` if the bytes left are only the start of one.
+    fn next(&mut self) -> Option<Self::Item> {
+        self.decode(false)
+    }
+}
 
-        let result = {
-            let mut rest = rest_bytes.inspect(|byte| {
-                if let &Ok(b) = byte {
-                    buffer.push(b);
+/// Reads events from a source of bytes.
+pub struct EventsIter<R> {
+    source: R,
+    /// The terminal the source reads from, which is polled to wait for the rest of an escape
+    /// sequence. Other sources are read until they end.
+    fd: Option<RawFd>,
+    decoder: Decoder,
+    /// Events read while waiting for the reply to a query, which are returned first.
+    queued: VecDeque<Event>,
+    /// How long to wait for the next byte of an unfinished escape sequence before taking the
+    /// sequence as it is.
+    pub escape_delay: Duration,
+}
+
+impl<R: Read> EventsIter<R> {
+    pub fn new(source: R) -> Self {
+        Self {
+            source,
+            fd: None,
+            decoder: Decoder::new(),
+            queued: VecDeque::new(),
+            escape_delay: DEFAULT_ESCAPE_DELAY,
+        }
+    }
+
+    /// Writes `request` to the terminal and returns the first reply `accept` takes. The keys
+    /// and other events read before it are kept for `next`, so that typing ahead of the reply
+    /// loses nothing.
+    pub fn query<T>(
+        &mut self,
+        output: &mut impl Write,
+        request: &str,
+        mut accept: impl FnMut(Response) -> Option<T>,
+    ) -> std::io::Result<T> {
+        write!(output, "{}", request)?;
+        output.flush()?;
+
+        loop {
+            match self.decode() {
+                Some(Ok(Event::Response(response))) => {
+                    if let Some(reply) = accept(response) {
+                        return Ok(reply);
+                    }
                 }
-            });
-
-            match byte {
-                0x00 => Ok(Event::Key(Key::Null)),
-                b'\n' | b'\r' => Ok(Event::Key(Key::Character("\n".into()))),
-                b @ 0x01..=0x1a => Ok(Event::Key(Key::Ctrl(
-                    ((b - 0x01 + b'a') as char)
-                        .encode_utf8(&mut [0])
-                        .to_owned()
-                        .into(),
-                ))),
-                b'\t' => Ok(Event::Key(Key::Character("\t".into()))),
-                0x1b => Self::parse_escape_sequence(rest_bytes),
-                b @ 0x1c..=0x1f => Ok(Event::Key(Key::Ctrl(
-                    ((b - 0x1c + b'4') as char)
-                        .encode_utf8(&mut [0])
-                        .to_owned()
-                        .into(),
-                ))),
-                0x7f => Ok(Event::Key(Key::Backspace)),
-                b => Ok(Event::Key(Key::Character(Self::parse_utf8(b, rest_bytes)?))),
+                Some(Ok(event)) => self.queued.push_back(event),
+                Some(Err(error)) => return Err(error),
+                None => return Err(std::io::ErrorKind::UnexpectedEof.into()),
             }
+        }
+    }
+
+    /// Waits until an event can be read without blocking, one of `fds` is readable, or
+    /// `timeout` passes. A source that is not a terminal is taken to be always readable.
+    pub fn wait(&self, fds: &[RawFd], timeout: Option<Duration>) -> std::io::Result<Ready> {
+        if !self.decoder.is_empty() || !self.queued.is_empty() {
+            return Ok(Ready::Input);
+        }
+        self.poll(fds, timeout)
+    }
+
+    fn poll(&self, fds: &[RawFd], timeout: Option<Duration>) -> std::io::Result<Ready> {
+        let Some(fd) = self.fd else {
+            return Ok(Ready::Input);
         };
 
-        result.or(Ok(Event::Unknown(buffer)))
+        let mut pollfds: Vec<libc::pollfd> = [fd]
+            .iter()
+            .chain(fds)
+            .map(|&fd| libc::pollfd {
+                fd,
+                events: libc::POLLIN,
+                revents: 0,
+            })
+            .collect();
+        let timeout = timeout.map_or(-1, |timeout| {
+            timeout
+                .as_micros()
+                .div_ceil(1000)
+                .min(libc::c_int::MAX as u128) as libc::c_int
+        });
+        loop {
+            let ready =
+                unsafe { libc::poll(pollfds.as_mut_ptr(), pollfds.len() as libc::nfds_t, timeout) };
+            if ready == -1 {
+                match std::io::Error::last_os_error() {
+                    error if error.kind() == std::io::ErrorKind::Interrupted => continue,
+                    error => return Err(error),
+                }
+            }
+            return Ok(
+                match pollfds.iter().position(|pollfd| pollfd.revents != 0) {
+                    Some(0) => Ready::Input,
+                    Some(index) => Ready::Fd(index - 1),
+                    None => Ready::Timeout,
+                },
+            );
+        }
     }
-}
 
-pub struct EventsIter<'a> {
-    stdin: &'a std::io::Stdin,
+    /// Feeds the decoder what the source has, waiting for at most `timeout`, and returns the
+    /// number of bytes read, which is 0 at the end of the source or when the timeout passes.
+    fn read(&mut self, timeout: Option<Duration>) -> std::io::Result<usize> {
+        if timeout.is_some() && self.poll(&[], timeout)? != Ready::Input {
+            return Ok(0);
+        }
+        let mut chunk = [0; 1024];
+        let read = self.source.read(&mut chunk)?;
+        self.decoder.feed(&chunk[..read]);
+        Ok(read)
+    }
+
+    /// Reads the next event from the source.
+    fn decode(&mut self) -> Option<std::io::Result<Event>> {
+        loop {
+            if let Some(event) = self.decoder.next() {
+                return Some(Ok(event));
+            }
+            // The rest of an unfinished sequence is waited for only as long as the escape delay,
+            // but a paste or control string that has started is waited for until it ends.
+            let timeout = (!self.decoder.is_empty() && !self.decoder.in_string())
+                .then_some(self.escape_delay);
+            match self.read(timeout) {
+                Ok(0) => return self.decoder.flush().map(Ok),
+                Ok(_) => {}
+                Err(error) => return Some(Err(error)),
+            }
+        }
+    }
 }
 
-impl<'a> Iterator for EventsIter<'a> {
-    type Item = std::io::Result<Event<'a>>;
+impl<R: Read> Iterator for EventsIter<R> {
+    type Item = std::io::Result<Event>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        // Read two bytes at a time to distinguish between single escape presses and escape sequences.
-        let mut buffer: [u8; 2] = [0; 2];
-
-        match self.stdin.read(&mut buffer) {
-            Ok(0) => None,
-            Ok(1) => match buffer[0] {
-                0x1b => Some(Ok(Event::Key(Key::Esc))),
-                byte => Some(Event::parse(byte, &mut self.stdin.bytes())),
-            },
-            Ok(2) => Some(Event::parse(
-                buffer[0],
-                &mut [Ok(buffer[1])].into_iter().chain(self.stdin.bytes()),
-            )),
-            Ok(_) => unreachable!(),
-            Err(error) => Some(Err(error)),
+        match self.queued.pop_front() {
+            Some(event) => Some(Ok(event)),
+            None => self.decode(),
         }
     }
 }
 
-pub trait Events<'a> {
-    fn events(&self) -> EventsIter;
+/// What `EventsIter::wait` stopped waiting for.
+#[derive(Debug, PartialEq)]
+pub enum Ready {
+    Input,
+    /// The file descriptor at this index was readable.
+    Fd(usize),
+    Timeout,
+}
+
+/// Reads events from a terminal.
+///
+/// The terminal must be read without buffering, such as through a `File` rather than
+/// `std::io::Stdin`, for polling it to see every byte that was not read yet.
+pub trait Events: Read + AsFd + Sized {
+    fn events(self) -> EventsIter<Self> {
+        let fd = self.as_fd().as_raw_fd();
+        EventsIter {
+            fd: Some(fd),
+            ..EventsIter::new(self)
+        }
+    }
 }
 
-impl<'a> Events<'a> for std::io::Stdin {
-    fn events(&self) -> EventsIter {
-        EventsIter { stdin: self }
+impl<R: Read + AsFd> Events for R {}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        alloc::{GlobalAlloc, Layout, System},
+        cell::Cell,
+    };
+
+    use super::*;
+
+    /// Counts the bytes each thread has allocated and not freed, so that a test can tell
+    /// whether what it does leaks while other tests run.
+    struct Counting;
+
+    thread_local! {
+        static ALLOCATED: Cell<isize> = const { Cell::new(0) };
+    }
+
+    unsafe impl GlobalAlloc for Counting {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let _ = ALLOCATED
+                .try_with(|allocated| allocated.set(allocated.get() + layout.size() as isize));
+            unsafe { System.alloc(layout) }
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            let _ = ALLOCATED
+                .try_with(|allocated| allocated.set(allocated.get() - layout.size() as isize));
+            unsafe { System.dealloc(ptr, layout) }
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: Counting = Counting;
+
+    /// A xorshift generator, so that a failing case can be replayed from its seed.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+
+        fn pick<T: Copy>(&mut self, items: &[T]) -> T {
+            items[self.below(items.len())]
+        }
+    }
+
+    /// Parses `input` to its end, returning the events with the bytes each one consumed.
+    fn parse_all(input: &[u8]) -> Vec<(Event, Vec<u8>)> {
+        let mut bytes = Bytes::new(input.iter().map(|&byte| Ok(byte)));
+        let mut events = Vec::new();
+        while let Some(event) = Event::parse(&mut bytes).unwrap() {
+            events.push((event, bytes.consumed.clone()));
+        }
+        events
+    }
+
+    fn parse_events(input: &[u8]) -> Vec<Event> {
+        parse_all(input)
+            .into_iter()
+            .map(|(event, _)| event)
+            .collect()
+    }
+
+    /// Returns random bytes, mostly ones that start or continue escape sequences.
+    fn arbitrary_bytes(rng: &mut Rng) -> Vec<u8> {
+        let len = rng.below(48);
+        (0..len)
+            .map(|_| match rng.below(4) {
+                0 => rng.next() as u8,
+                1 => rng.pick(b"\x1b[O<M~;:?>0123456789"),
+                2 => rng.pick(&[0x1b, b'[', 0xc3, 0xe2, 0xf0, 0x80, 0xbf, 0x7f, 0x00]),
+                _ => rng.pick(b"ABCDFHPZmMu~1;25"),
+            })
+            .collect()
+    }
+
+    /// Returns a well-formed sequence, known to the parser or not.
+    fn sequence(rng: &mut Rng) -> Vec<u8> {
+        loop {
+            let sequence = match rng.below(7) {
+                0 => {
+                    let c = char::from_u32(rng.below(0x11_0000) as u32).unwrap_or('é');
+                    c.to_string().into_bytes()
+                }
+                1 => [b"\x1bO".as_slice(), &[rng.pick(b"PQRSABCDFH")]].concat(),
+                2 => format!(
+                    "\x1b[<{};{};{}{}",
+                    rng.below(130),
+                    rng.below(300),
+                    rng.below(300),
+                    rng.pick(&['M', 'm'])
+                )
+                .into_bytes(),
+                3 => [b"\x1b[M".as_slice(), &rng.next().to_le_bytes()[..3]].concat(),
+                4 => format!("\x1b[{};{}~", rng.below(40), rng.below(20)).into_bytes(),
+                5 => {
+                    let text: String = (0..rng.below(20))
+                        .map(|_| char::from_u32(rng.below(0x800) as u32).unwrap_or('é'))
+                        .collect();
+                    Clipboard::copy(&text).into_bytes()
+                }
+                _ => {
+                    let mut sequence = b"\x1b[".to_vec();
+                    for _ in 0..rng.below(8) {
+                        sequence.push(rng.pick(b"0123456789;:<=>?"));
+                    }
+                    for _ in 0..rng.below(2) {
+                        sequence.push(rng.pick(b" !\"#$%&'()*+,-./"));
+                    }
+                    sequence.push(0x40 + rng.below(0x3f) as u8);
+                    sequence
+                }
+            };
+            // X10 mouse reports, console function keys and bracketed pastes go on past what
+            // would otherwise be the final byte.
+            if matches!(sequence.as_slice(), b"\x1b[M" | b"\x1b[[" | b"\x1b[200~") {
+                continue;
+            }
+            return sequence;
+        }
+    }
+
+    #[test]
+    fn arbitrary_bytes_are_consumed_exactly_once() {
+        let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+        for _ in 0..100_000 {
+            let input = arbitrary_bytes(&mut rng);
+            let events = parse_all(&input);
+            let consumed: Vec<u8> = events.iter().flat_map(|(_, bytes)| bytes.clone()).collect();
+            assert_eq!(consumed, input);
+            for (event, bytes) in events {
+                assert!(!bytes.is_empty(), "{:?} from {:?}", event, input);
+                if let Event::Unknown(unknown) = event {
+                    assert_eq!(unknown, bytes);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn sequences_end_where_they_end() {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+        for _ in 0..100_000 {
+            let sequence = sequence(&mut rng);
+            let input = [sequence.as_slice(), b"x"].concat();
+            let events = parse_all(&input);
+            assert_eq!(events.len(), 2, "{:?}", input);
+            assert_eq!(events[0].1, sequence);
+            assert_eq!(
+                events[1].0,
+                Event::Key(Key::Character('x'), Modifiers::NONE, KeyKind::Press)
+            );
+        }
+    }
+
+    #[test]
+    fn malformed_sequences_leave_the_next_one_alone() {
+        let events = parse_events(b"\x1b[1;5\x1b[A\xc3a\x1b[<0;1M");
+        assert_eq!(
+            events,
+            [
+                Event::Unknown(b"\x1b[1;5".to_vec()),
+                Event::Key(Key::Up, Modifiers::NONE, KeyKind::Press),
+                Event::Unknown(b"\xc3".to_vec()),
+                Event::Key(Key::Character('a'), Modifiers::NONE, KeyKind::Press),
+                Event::Unknown(b"\x1b[<0;1M".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn modifiers_are_decoded_for_every_key() {
+        let events = parse_events(b"\x1b[3;2~\x1b[5;7~\x1b[15;5~\x1b[1;4P\x1b[1;9A\x1b\x1bx\x01");
+        assert_eq!(
+            events,
+            [
+                Event::Key(Key::Delete, Modifiers::SHIFT, KeyKind::Press),
+                Event::Key(
+                    Key::PageUp,
+                    Modifiers::CTRL | Modifiers::ALT,
+                    KeyKind::Press
+                ),
+                Event::Key(Key::F(5), Modifiers::CTRL, KeyKind::Press),
+                Event::Key(Key::F(1), Modifiers::SHIFT | Modifiers::ALT, KeyKind::Press),
+                Event::Key(Key::Up, Modifiers::META, KeyKind::Press),
+                Event::Key(Key::Esc, Modifiers::ALT, KeyKind::Press),
+                Event::Key(Key::Character('x'), Modifiers::NONE, KeyKind::Press),
+                Event::Key(Key::Character('a'), Modifiers::CTRL, KeyKind::Press),
+            ]
+        );
+    }
+
+    #[test]
+    fn kitty_keys_are_disambiguated() {
+        let events = parse_events(
+            b"\x1b[27u\x1b[105;5u\t\x1b[13;5u\x1b[1092::97;5u\x1b[97:65;2u\x1b[1;5:3A\x1b[3;1:2~",
+        );
+        assert_eq!(
+            events,
+            [
+                Event::Key(Key::Esc, Modifiers::NONE, KeyKind::Press),
+                Event::Key(Key::Character('i'), Modifiers::CTRL, KeyKind::Press),
+                Event::Key(Key::Character('\t'), Modifiers::NONE, KeyKind::Press),
+                Event::Key(Key::Character('\n'), Modifiers::CTRL, KeyKind::Press),
+                Event::Key(Key::Character('a'), Modifiers::CTRL, KeyKind::Press),
+                Event::Key(Key::Character('A'), Modifiers::SHIFT, KeyKind::Press),
+                Event::Key(Key::Up, Modifiers::CTRL, KeyKind::Release),
+                Event::Key(Key::Delete, Modifiers::NONE, KeyKind::Repeat),
+            ]
+        );
+    }
+
+    #[test]
+    fn clipboard_contents_are_decoded() {
+        let events =
+            parse_events(b"\x1b]52;c;aMOpbGzDsw==\x07\x1b]52;p;\x1b\\\x1b]52;c;a\x07\x1b]");
+        assert_eq!(
+            events,
+            [
+                Event::Clipboard("hélló".to_string()),
+                Event::Clipboard(String::new()),
+                Event::Unknown(b"\x1b]52;c;a\x07".to_vec()),
+                Event::Key(Key::Character(']'), Modifiers::ALT, KeyKind::Press),
+            ]
+        );
+        for text in ["", "a", "ab", "abc", "line\nbreak ✓"] {
+            let events = parse_events(Clipboard::copy(text).as_bytes());
+            assert_eq!(events[0], Event::Clipboard(text.to_string()));
+        }
+    }
+
+    #[test]
+    fn terminal_replies_are_told_from_keys() {
+        let events = parse_events(
+            b"\x1b[12;40R\x1b[1;5R\x1b[?62;22c\x1b[>1;10;0c\x1b[?7u\x1b[I\x1b[O\x1bP>|XTerm(367)\x1b\\\
+              \x1b]11;rgb:ffff/8080/0000\x1b\\\x1b]4;1;rgb:f/80/000\x07\x1bPx\x1b]10;red\x07",
+        );
+        let response = Event::Response;
+        assert_eq!(
+            events,
+            [
+                response(Response::CursorPosition(12, 40)),
+                response(Response::CursorPosition(1, 5)),
+                response(Response::PrimaryAttributes(vec![62, 22])),
+                response(Response::SecondaryAttributes(vec![1, 10, 0])),
+                response(Response::KeyboardFlags(7)),
+                Event::FocusGained,
+                Event::FocusLost,
+                response(Response::Version("XTerm(367)".to_string())),
+                response(Response::Color(
+                    TerminalColor::Background,
+                    [0xffff, 0x8080, 0]
+                )),
+                response(Response::Color(
+                    TerminalColor::Palette(1),
+                    [0xffff, 0x8080, 0]
+                )),
+                Event::Key(Key::Character('P'), Modifiers::ALT, KeyKind::Press),
+                Event::Key(Key::Character('x'), Modifiers::NONE, KeyKind::Press),
+                Event::Unknown(b"\x1b]10;red\x07".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn keystrokes_split_across_reads_are_joined() {
+        let mut decoder = Decoder::new();
+        decoder.feed(b"a\x1b[1;");
+        assert_eq!(
+            decoder.next(),
+            Some(Event::Key(
+                Key::Character('a'),
+                Modifiers::NONE,
+                KeyKind::Press
+            ))
+        );
+        assert_eq!(decoder.next(), None);
+        decoder.feed(b"5C\xc3");
+        assert_eq!(
+            decoder.next(),
+            Some(Event::Key(Key::Right, Modifiers::CTRL, KeyKind::Press))
+        );
+        assert_eq!(decoder.next(), None);
+        decoder.feed(b"\xa9");
+        assert_eq!(
+            decoder.next(),
+            Some(Event::Key(
+                Key::Character('é'),
+                Modifiers::NONE,
+                KeyKind::Press
+            ))
+        );
+        assert!(decoder.is_empty());
+    }
+
+    #[test]
+    fn pastes_and_control_strings_split_across_reads_are_joined() {
+        let mut decoder = Decoder::new();
+        decoder.feed(b"\x1b[200~");
+        assert_eq!(decoder.next(), None);
+        for _ in 0..1000 {
+            decoder.feed(b"ab\r");
+            assert_eq!(decoder.next(), None);
+        }
+        decoder.feed(b"\x1b[20");
+        assert_eq!(decoder.next(), None);
+        decoder.feed(b"1~x");
+        assert_eq!(decoder.next(), Some(Event::Paste("ab\n".repeat(1000))));
+        assert_eq!(
+            decoder.next(),
+            Some(Event::Key(
+                Key::Character('x'),
+                Modifiers::NONE,
+                KeyKind::Press
+            ))
+        );
+
+        decoder.feed(b"\x1b]52;c;aMOp");
+        assert_eq!(decoder.next(), None);
+        decoder.feed(b"bGzDsw==\x1b");
+        assert_eq!(decoder.next(), None);
+        decoder.feed(b"\\");
+        assert_eq!(decoder.next(), Some(Event::Clipboard("hélló".to_owned())));
+        assert!(decoder.is_empty());
+    }
+
+    #[test]
+    fn only_escapes_time_out() {
+        use std::io::Write;
+
+        let (reader, mut writer) = std::io::pipe().unwrap();
+        let mut events = reader.events();
+        events.escape_delay = Duration::from_millis(10);
+        let typist = std::thread::spawn(move || {
+            for bytes in [
+                &b"\x1b"[..],
+                b"\x1b[200~a",
+                b"b\x1b[201~\x1b]11;",
+                b"rgb:0/0/0\x07",
+            ] {
+                writer.write_all(bytes).unwrap();
+                std::thread::sleep(Duration::from_millis(50));
+            }
+        });
+
+        let events: Vec<Event> = events.collect::<std::io::Result<_>>().unwrap();
+        typist.join().unwrap();
+        assert_eq!(
+            events,
+            [
+                Event::Key(Key::Esc, Modifiers::NONE, KeyKind::Press),
+                Event::Paste("ab".to_owned()),
+                Event::Response(Response::Color(TerminalColor::Background, [0, 0, 0])),
+            ]
+        );
+    }
+
+    #[test]
+    fn unfinished_sequences_are_taken_as_they_are_when_flushed() {
+        let mut decoder = Decoder::new();
+        decoder.feed(b"\x1b");
+        assert_eq!(decoder.next(), None);
+        assert_eq!(
+            decoder.flush(),
+            Some(Event::Key(Key::Esc, Modifiers::NONE, KeyKind::Press))
+        );
+        decoder.feed(b"\x1bO");
+        assert_eq!(decoder.next(), None);
+        assert_eq!(
+            decoder.flush(),
+            Some(Event::Key(
+                Key::Character('O'),
+                Modifiers::ALT,
+                KeyKind::Press
+            ))
+        );
+        decoder.feed(b"\x1b[1;");
+        assert_eq!(decoder.flush(), Some(Event::Unknown(b"\x1b[1;".to_vec())));
+        assert_eq!(decoder.flush(), None);
+    }
+
+    #[test]
+    fn events_are_read_from_any_source() {
+        let events: Vec<Event> = EventsIter::new(&b"ls\r\x1b[A\x1b"[..])
+            .collect::<std::io::Result<_>>()
+            .unwrap();
+        assert_eq!(
+            events,
+            [
+                Event::Key(Key::Character('l'), Modifiers::NONE, KeyKind::Press),
+                Event::Key(Key::Character('s'), Modifiers::NONE, KeyKind::Press),
+                Event::Key(Key::Character('\n'), Modifiers::NONE, KeyKind::Press),
+                Event::Key(Key::Up, Modifiers::NONE, KeyKind::Press),
+                Event::Key(Key::Esc, Modifiers::NONE, KeyKind::Press),
+            ]
+        );
+    }
+
+    #[test]
+    fn keys_typed_ahead_of_a_reply_are_kept() {
+        let mut events = EventsIter::new(&b"ab\x1b[200~c\x1b[201~\x1b[3;5Rd"[..]);
+        let mut output = Vec::new();
+        let position = events.query(&mut output, "\x1b[6n", |response| match response {
+            Response::CursorPosition(row, column) => Some((row, column)),
+            _ => None,
+        });
+        assert_eq!(position.unwrap(), (3, 5));
+        assert_eq!(output, b"\x1b[6n");
+
+        let key = |c| Event::Key(Key::Character(c), Modifiers::NONE, KeyKind::Press);
+        let events: Vec<Event> = events.collect::<std::io::Result<_>>().unwrap();
+        assert_eq!(
+            events,
+            [key('a'), key('b'), Event::Paste("c".to_owned()), key('d')]
+        );
+    }
+
+    #[test]
+    fn memory_stays_flat_over_millions_of_keystrokes() {
+        let keystrokes = "echo héllo wörld\r\x1b[A\x1b[1;5C\x7f\x01\x1bx\x1b[97;5u".as_bytes();
+        let mut decoder = Decoder::new();
+        let mut decoded = 0;
+        let mut type_keystrokes = |times: usize| {
+            for _ in 0..times {
+                decoder.feed(keystrokes);
+                decoded += decoder.by_ref().count();
+            }
+        };
+
+        type_keystrokes(1_000);
+        let allocated = ALLOCATED.with(Cell::get);
+        type_keystrokes(100_000);
+        assert_eq!(ALLOCATED.with(Cell::get), allocated);
+        assert_eq!(decoded, 101_000 * 23);
+    }
+
+    #[test]
+    fn mouse_reports_carry_buttons_motion_and_modifiers() {
+        let events = parse_events(
+            b"\x1b[<0;3;4M\x1b[<32;5;4M\x1b[<0;5;4m\x1b[<35;6;7M\x1b[<20;1;1M\x1b[<128;2;2M\x1b[<1000;9;9M\x1b[M`!!\x1b[M#\xff\xff\x1b[33;10;20M",
+        );
+        assert_eq!(
+            events,
+            [
+                Event::Mouse(Mouse::Press(MouseButton::Left, 3, 4), Modifiers::NONE),
+                Event::Mouse(Mouse::Drag(MouseButton::Left, 5, 4), Modifiers::NONE),
+                Event::Mouse(
+                    Mouse::Release(Some(MouseButton::Left), 5, 4),
+                    Modifiers::NONE
+                ),
+                Event::Mouse(Mouse::Move(6, 7), Modifiers::NONE),
+                Event::Mouse(
+                    Mouse::Press(MouseButton::Left, 1, 1),
+                    Modifiers::SHIFT | Modifiers::CTRL
+                ),
+                Event::Mouse(Mouse::Press(MouseButton::Back, 2, 2), Modifiers::NONE),
+                Event::Unknown(b"\x1b[<1000;9;9M".to_vec()),
+                Event::Mouse(Mouse::Press(MouseButton::WheelUp, 1, 1), Modifiers::NONE),
+                Event::Mouse(Mouse::Release(None, 223, 223), Modifiers::NONE),
+                Event::Mouse(Mouse::Press(MouseButton::Middle, 10, 20), Modifiers::NONE),
+            ]
+        );
     }
 }
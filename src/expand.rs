@@ -0,0 +1,564 @@
+use std::{ffi::CStr, ffi::CString, fmt, io, mem};
+
+use crate::{lexer, pattern, shell::Shell};
+
+/// An expansion that cannot be performed, such as `${name?}` of an unset variable. It aborts
+/// the command being expanded, and is carried by an `io::Error` up to where the command was
+/// executed from.
+#[derive(Debug)]
+pub struct Error(String);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Whether `error` is an expansion error rather than a failure of the system.
+pub fn is_error(error: &io::Error) -> bool {
+    error.get_ref().is_some_and(|inner| inner.is::<Error>())
+}
+
+fn error(message: String) -> io::Error {
+    io::Error::other(Error(message))
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Char {
+    c: char,
+    quoted: bool,
+}
+
+/// Expands a word into fields: tilde expansion, parameter expansion, command substitution,
+/// field splitting, pathname expansion and quote removal.
+pub fn fields(shell: &mut Shell, word: &str) -> io::Result<Vec<String>> {
+    let mut expander = Expander::new(shell, true);
+    expander.word(word)?;
+
+    let mut fields = Vec::new();
+    for field in expander.finish() {
+        if field
+            .iter()
+            .any(|c| !c.quoted && matches!(c.c, '*' | '?' | '['))
+        {
+            let paths = pattern::glob(&pattern_text(&field));
+            if !paths.is_empty() {
+                fields.extend(paths);
+                continue;
+            }
+        }
+        fields.push(plain_text(&field));
+    }
+    Ok(fields)
+}
+
+/// Expands a word into a single string, without field splitting and pathname expansion.
+pub fn string(shell: &mut Shell, word: &str) -> io::Result<String> {
+    let mut expander = Expander::new(shell, false);
+    expander.word(word)?;
+    Ok(plain_text(&expander.finish().concat()))
+}
+
+/// Expands a word into a pattern, in which the quoted characters are escaped.
+pub fn pattern(shell: &mut Shell, word: &str) -> io::Result<String> {
+    let mut expander = Expander::new(shell, false);
+    expander.word(word)?;
+    Ok(pattern_text(&expander.finish().concat()))
+}
+
+fn plain_text(chars: &[Char]) -> String {
+    chars.iter().map(|c| c.c).collect()
+}
+
+fn pattern_text(chars: &[Char]) -> String {
+    let mut text = String::with_capacity(chars.len());
+    for c in chars {
+        if c.quoted && matches!(c.c, '*' | '?' | '[' | ']' | '\\') {
+            text.push('\\');
+        }
+        text.push(c.c);
+    }
+    text
+}
+
+struct Expander<'a> {
+    shell: &'a mut Shell,
+    split: bool,
+    fields: Vec<Vec<Char>>,
+    current: Vec<Char>,
+    /// Whether the current field is kept even if empty, as for `""`.
+    keep: bool,
+}
+
+impl<'a> Expander<'a> {
+    fn new(shell: &'a mut Shell, split: bool) -> Self {
+        Self {
+            shell,
+            split,
+            fields: Vec::new(),
+            current: Vec::new(),
+            keep: false,
+        }
+    }
+
+    fn finish(mut self) -> Vec<Vec<Char>> {
+        self.delimit();
+        self.fields
+    }
+
+    fn delimit(&mut self) {
+        if !self.current.is_empty() || self.keep {
+            self.fields.push(mem::take(&mut self.current));
+            self.keep = false;
+        }
+    }
+
+    fn push_str(&mut self, str: &str, quoted: bool) {
+        self.current.extend(str.chars().map(|c| Char { c, quoted }));
+    }
+
+    /// Pushes the result of an expansion, splitting it into fields unless it is quoted.
+    fn push_expansion(&mut self, value: &str, quoted: bool) {
+        if quoted || !self.split {
+            self.push_str(value, quoted);
+            return;
+        }
+
+        let ifs = self
+            .shell
+            .parameter("IFS")
+            .unwrap_or_else(|| String::from(" \t\n"));
+        for c in value.chars() {
+            if ifs.contains(c) {
+                self.delimit();
+            } else {
+                self.current.push(Char { c, quoted: false });
+            }
+        }
+    }
+
+    fn word(&mut self, word: &str) -> io::Result<()> {
+        let bytes = word.as_bytes();
+        let mut i = self.tilde(word);
+
+        while i < bytes.len() {
+            match bytes[i] {
+                b'\\' => {
+                    let rest = &word[i + 1..];
+                    match rest.chars().next() {
+                        Some('\n') => {}
+                        Some(c) => self.current.push(Char { c, quoted: true }),
+                        None => self.push_str("\\", false),
+                    }
+                    i += 1 + rest.chars().next().map_or(0, char::len_utf8);
+                }
+                b'\'' => {
+                    let end = lexer::scan_single_quotes(bytes, i).unwrap_or(bytes.len());
+                    self.push_str(word[i + 1..end].trim_end_matches('\''), true);
+                    self.keep = true;
+                    i = end;
+                }
+                b'"' => {
+                    let end = lexer::scan_double_quotes(bytes, i).unwrap_or(bytes.len());
+                    let inner = &word[i + 1..end];
+                    let inner = inner.strip_suffix('"').unwrap_or(inner);
+                    self.double_quotes(inner)?;
+                    // `"$@"` expands to no field at all when there are no positional parameters.
+                    self.keep = inner != "$@" || !self.shell.positional.is_empty();
+                    i = end;
+                }
+                b'`' => i = self.backquotes(word, i, false)?,
+                b'$' => i = self.dollar(word, i, false)?,
+                _ => {
+                    let c = word[i..].chars().next().unwrap_or_default();
+                    self.current.push(Char { c, quoted: false });
+                    i += c.len_utf8();
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn double_quotes(&mut self, inner: &str) -> io::Result<()> {
+        let bytes = inner.as_bytes();
+        let mut i = 0;
+        while i < bytes.len() {
+            match bytes[i] {
+                b'\\' => match bytes.get(i + 1) {
+                    Some(b'\n') => i += 2,
+                    Some(&b @ (b'$' | b'`' | b'"' | b'\\')) => {
+                        self.current.push(Char {
+                            c: b as char,
+                            quoted: true,
+                        });
+                        i += 2;
+                    }
+                    _ => {
+                        self.push_str("\\", true);
+                        i += 1;
+                    }
+                },
+                b'`' => i = self.backquotes(inner, i, true)?,
+                b'$' => i = self.dollar(inner, i, true)?,
+                _ => {
+                    let c = inner[i..].chars().next().unwrap_or_default();
+                    self.current.push(Char { c, quoted: true });
+                    i += c.len_utf8();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Expands a leading `~` or `~user`, returning where the rest of the word starts.
+    fn tilde(&mut self, word: &str) -> usize {
+        let Some(rest) = word.strip_prefix('~') else {
+            return 0;
+        };
+        let user = &rest[..rest.find('/').unwrap_or(rest.len())];
+        if user.contains(['\'', '"', '\\', '$', '`']) {
+            return 0;
+        }
+
+        let home = if user.is_empty() {
+            self.shell.parameter("HOME")
+        } else {
+            home_directory(user)
+        };
+        match home {
+            Some(home) => {
+                self.push_str(&home, true);
+                1 + user.len()
+            }
+            None => 0,
+        }
+    }
+
+    fn backquotes(&mut self, word: &str, start: usize, quoted: bool) -> io::Result<usize> {
+        let end = lexer::scan_backquotes(word.as_bytes(), start).unwrap_or(word.len());
+        let inner = word[start + 1..end].trim_end_matches('`');
+
+        let mut source = String::with_capacity(inner.len());
+        let mut chars = inner.chars().peekable();
+        while let Some(c) = chars.next() {
+            match (c, chars.peek()) {
+                ('\\', Some(&next @ ('$' | '`' | '\\'))) => {
+                    source.push(next);
+                    chars.next();
+                }
+                (c, _) => source.push(c),
+            }
+        }
+
+        let output = self.shell.capture(&source)?;
+        self.push_expansion(&output, quoted);
+        Ok(end)
+    }
+
+    fn dollar(&mut self, word: &str, start: usize, quoted: bool) -> io::Result<usize> {
+        let bytes = word.as_bytes();
+        match bytes.get(start + 1) {
+            Some(b'(') if bytes.get(start + 2) == Some(&b'(') => {
+                let end = lexer::scan_dollar(bytes, start).unwrap_or(bytes.len());
+                Err(error(format!(
+                    "{}: arithmetic expansion is not supported",
+                    &word[start..end]
+                )))
+            }
+            Some(b'(') => {
+                let end = lexer::scan_dollar(bytes, start).unwrap_or(bytes.len());
+                let source = word[start + 2..end].strip_suffix(')').unwrap_or("");
+                let output = self.shell.capture(source)?;
+                self.push_expansion(&output, quoted);
+                Ok(end)
+            }
+            Some(b'{') => {
+                let end = lexer::scan_dollar(bytes, start).unwrap_or(bytes.len());
+                let inner = word[start + 2..end].strip_suffix('}').unwrap_or("");
+                self.braces(inner, quoted)?;
+                Ok(end)
+            }
+            Some(b'@') if quoted => {
+                let positional = self.shell.positional.clone();
+                for (index, parameter) in positional.iter().enumerate() {
+                    if index > 0 && self.split {
+                        self.fields.push(mem::take(&mut self.current));
+                    } else if index > 0 {
+                        self.push_str(" ", true);
+                    }
+                    self.push_str(parameter, true);
+                }
+                Ok(start + 2)
+            }
+            Some(&b) => {
+                let length = match b {
+                    b'@' | b'*' | b'#' | b'?' | b'-' | b'$' | b'!' | b'0'..=b'9' => 1,
+                    _ => word[start + 1..]
+                        .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                        .unwrap_or(word.len() - start - 1),
+                };
+                let name = &word[start + 1..start + 1 + length];
+                if name.is_empty() {
+                    self.push_str("$", quoted);
+                } else {
+                    let value = self.shell.parameter(name).unwrap_or_default();
+                    self.push_expansion(&value, quoted);
+                }
+                Ok(start + 1 + length)
+            }
+            None => {
+                self.push_str("$", quoted);
+                Ok(start + 1)
+            }
+        }
+    }
+
+    /// Expands the inside of `${...}`.
+    fn braces(&mut self, inner: &str, quoted: bool) -> io::Result<()> {
+        if let Some(name) = inner.strip_prefix('#').filter(|name| !name.is_empty()) {
+            let length = self
+                .shell
+                .parameter(name)
+                .map_or(0, |value| value.chars().count());
+            self.push_expansion(&length.to_string(), quoted);
+            return Ok(());
+        }
+
+        let length = match inner.bytes().next() {
+            Some(b'0'..=b'9') => inner.bytes().take_while(u8::is_ascii_digit).count(),
+            Some(b'@' | b'*' | b'#' | b'?' | b'-' | b'$' | b'!') => 1,
+            _ => inner
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(inner.len()),
+        };
+        let (name, rest) = inner.split_at(length);
+        if name.is_empty() {
+            return Err(bad_substitution(inner));
+        }
+
+        let value = self.shell.parameter(name);
+        let (colon, operation) = match rest.strip_prefix(':') {
+            Some(operation) => (true, operation),
+            None => (false, rest),
+        };
+        let null = match &value {
+            None => true,
+            Some(value) => colon && value.is_empty(),
+        };
+
+        let Some(operator) = operation.chars().next() else {
+            if colon {
+                return Err(bad_substitution(inner));
+            }
+            self.push_expansion(&value.unwrap_or_default(), quoted);
+            return Ok(());
+        };
+        let word = &operation[operator.len_utf8()..];
+
+        let result = match operator {
+            '-' if null => string(self.shell, word)?,
+            '=' if null => {
+                let word = string(self.shell, word)?;
+                if lexer::is_name(name) {
+                    self.shell.variables.set(name, word.as_str());
+                }
+                word
+            }
+            '?' if null => {
+                let word = string(self.shell, word)?;
+                let message = if word.is_empty() {
+                    "parameter null or not set"
+                } else {
+                    &word
+                };
+                return Err(error(format!("{}: {}", name, message)));
+            }
+            '+' if null => String::new(),
+            '+' => string(self.shell, word)?,
+            '-' | '=' | '?' => value.unwrap_or_default(),
+            '%' | '#' if !colon => {
+                let value = value.unwrap_or_default();
+                let longest = word.starts_with(operator);
+                let pattern = pattern(self.shell, if longest { &word[1..] } else { word })?;
+                remove_pattern(&value, &pattern, operator == '#', longest)
+            }
+            _ => return Err(bad_substitution(inner)),
+        };
+
+        self.push_expansion(&result, quoted);
+        Ok(())
+    }
+}
+
+fn bad_substitution(inner: &str) -> io::Error {
+    error(format!("${{{}}}: bad substitution", inner))
+}
+
+/// Removes the shortest or longest prefix or suffix of `value` matching `pattern`.
+fn remove_pattern(value: &str, pattern: &str, prefix: bool, longest: bool) -> String {
+    let mut boundaries: Vec<usize> = value
+        .char_indices()
+        .map(|(offset, _)| offset)
+        .chain([value.len()])
+        .collect();
+
+    if prefix {
+        if longest {
+            boundaries.reverse();
+        }
+        for end in boundaries {
+            if pattern::matches(pattern, &value[..end]) {
+                return value[end..].to_owned();
+            }
+        }
+    } else {
+        if !longest {
+            boundaries.reverse();
+        }
+        for start in boundaries {
+            if pattern::matches(pattern, &value[start..]) {
+                return value[..start].to_owned();
+            }
+        }
+    }
+
+    value.to_owned()
+}
+
+pub fn home_directory(user: &str) -> Option<String> {
+    let user = CString::new(user).ok()?;
+    unsafe {
+        let passwd = libc::getpwnam(user.as_ptr());
+        if passwd.is_null() {
+            return None;
+        }
+        Some(
+            CStr::from_ptr((*passwd).pw_dir)
+                .to_string_lossy()
+                .into_owned(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        io::{Read, Seek, SeekFrom},
+        rc::Rc,
+    };
+
+    use super::*;
+    use crate::shell::{self, Streams};
+
+    fn expand(shell: &mut Shell, word: &str) -> String {
+        string(shell, word).unwrap()
+    }
+
+    fn expansion_error(shell: &mut Shell, word: &str) -> String {
+        let error = string(shell, word).unwrap_err();
+        assert!(is_error(&error), "{}", word);
+        error.to_string()
+    }
+
+    #[test]
+    fn parameter_operators_tell_unset_from_null() {
+        let mut shell = Shell::for_tests();
+        shell.variables.set("null", "");
+        shell.variables.set("set", "value");
+
+        assert_eq!(expand(&mut shell, "${unset-default}"), "default");
+        assert_eq!(expand(&mut shell, "${null-default}"), "");
+        assert_eq!(expand(&mut shell, "${null:-default}"), "default");
+        assert_eq!(expand(&mut shell, "${set:-default}"), "value");
+
+        assert_eq!(expand(&mut shell, "${unset+alternative}"), "");
+        assert_eq!(expand(&mut shell, "${null+alternative}"), "alternative");
+        assert_eq!(expand(&mut shell, "${null:+alternative}"), "");
+        assert_eq!(expand(&mut shell, "${set:+alternative}"), "alternative");
+
+        assert_eq!(expand(&mut shell, "${assigned=first}"), "first");
+        assert_eq!(expand(&mut shell, "${assigned=second}"), "first");
+        assert_eq!(shell.variables.get("assigned"), Some("first"));
+        assert_eq!(expand(&mut shell, "${null:=filled}"), "filled");
+
+        assert_eq!(expand(&mut shell, "${set:?}"), "value");
+        assert_eq!(
+            expansion_error(&mut shell, "${unset?}"),
+            "unset: parameter null or not set"
+        );
+        assert_eq!(
+            expansion_error(&mut shell, "${unset:?not here}"),
+            "unset: not here"
+        );
+    }
+
+    #[test]
+    fn patterns_are_removed_from_either_end() {
+        let mut shell = Shell::for_tests();
+        shell.variables.set("path", "/usr/lib/libé.so.1");
+
+        assert_eq!(expand(&mut shell, "${#path}"), "18");
+        assert_eq!(expand(&mut shell, "${path#*/}"), "usr/lib/libé.so.1");
+        assert_eq!(expand(&mut shell, "${path##*/}"), "libé.so.1");
+        assert_eq!(expand(&mut shell, "${path%.*}"), "/usr/lib/libé.so");
+        assert_eq!(expand(&mut shell, "${path%%.*}"), "/usr/lib/libé");
+        assert_eq!(expand(&mut shell, "${path%\\.so*}"), "/usr/lib/libé");
+        assert_eq!(expand(&mut shell, "${path#nothing}"), "/usr/lib/libé.so.1");
+    }
+
+    #[test]
+    fn unknown_operators_are_bad_substitutions() {
+        let mut shell = Shell::for_tests();
+        shell.variables.set("a", "value");
+        for word in ["${aé}", "${a:é}", "${a:}", "${a:#x}", "${}", "${é}"] {
+            let inner = &word[2..word.len() - 1];
+            assert_eq!(
+                expansion_error(&mut shell, word),
+                format!("${{{}}}: bad substitution", inner)
+            );
+        }
+    }
+
+    #[test]
+    fn arithmetic_expansion_is_rejected() {
+        let mut shell = Shell::for_tests();
+        for word in ["$((1 + 2))", "\"x$((1 + 2))\""] {
+            assert_eq!(
+                expansion_error(&mut shell, word),
+                "$((1 + 2)): arithmetic expansion is not supported"
+            );
+        }
+    }
+
+    #[test]
+    fn expansion_errors_abort_the_command() {
+        let mut shell = Shell::for_tests();
+        let (file, path) = shell::create_temp_file("oxide-film-test-", "").unwrap();
+        fs::remove_file(path).unwrap();
+        let file = Rc::new(file);
+        let streams = Streams {
+            stdout: Some(file.clone()),
+            stderr: Some(file.clone()),
+            ..Streams::default()
+        };
+
+        shell
+            .execute("alias a=1; alias; alias b=${unset?}; alias", &streams)
+            .unwrap();
+        assert_eq!(shell.status, 1);
+        shell.execute("alias", &streams).unwrap();
+        assert_eq!(shell.status, 0);
+
+        let mut output = String::new();
+        (&*file).seek(SeekFrom::Start(0)).unwrap();
+        (&*file).read_to_string(&mut output).unwrap();
+        assert_eq!(
+            output,
+            "alias a=1\noxide-film: unset: parameter null or not set\nalias a=1\n"
+        );
+    }
+}
@@ -0,0 +1,316 @@
+use std::{ops::Range, path::Path};
+
+use crate::{
+    builtins,
+    lexer::{self, Kind},
+    parser::{self, Error},
+    shell::{self, Shell},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Style {
+    Plain,
+    Builtin,
+    Function,
+    Alias,
+    Executable,
+    /// A command name that does not resolve to anything.
+    Missing,
+    Keyword,
+    String,
+    Variable,
+    Operator,
+    Redirection,
+    Comment,
+    /// An unterminated quote or substitution, or the token a syntax error is found at.
+    Error,
+    /// The suggested end of the line, which is not part of it yet.
+    Suggestion,
+    /// Text selected with the mouse.
+    Selection,
+}
+
+impl Style {
+    /// The parameters of the SGR sequence selecting the style.
+    pub fn sgr(self) -> &'static str {
+        match self {
+            Self::Plain => "0",
+            Self::Builtin => "1;32",
+            Self::Function | Self::Alias => "36",
+            Self::Executable => "32",
+            Self::Missing | Self::Error => "31",
+            Self::Keyword => "35",
+            Self::String => "33",
+            Self::Variable => "34",
+            Self::Operator => "1",
+            Self::Redirection => "1;36",
+            Self::Comment => "2",
+            Self::Suggestion => "90",
+            Self::Selection => "7",
+        }
+    }
+}
+
+/// Returns the style of each character of `line`.
+pub fn highlight(shell: &Shell, line: &str) -> Vec<Style> {
+    let bytes = line.as_bytes();
+    let mut styles = vec![Style::Plain; line.len()];
+
+    // Whether a command name, a redirection target, or the name and the `in` of a `for`
+    // clause is expected.
+    let mut command = true;
+    let mut redirection = false;
+    let mut for_clause = 0;
+
+    let tokens = lexer::tokenize(line);
+    for (index, token) in tokens.iter().enumerate() {
+        let span = token.span.clone();
+        match token.kind {
+            Kind::Comment => styles[span].fill(Style::Comment),
+            Kind::Operator => {
+                styles[span].fill(Style::Operator);
+                // The body of a function follows the `()` of its definition.
+                command = token.text != ")"
+                    || index
+                        .checked_sub(1)
+                        .is_some_and(|previous| tokens[previous].text == "(");
+                redirection = false;
+                for_clause = 0;
+            }
+            Kind::Redirection => {
+                styles[span].fill(Style::Redirection);
+                redirection = true;
+            }
+            Kind::Word if redirection => {
+                word(&mut styles, bytes, span);
+                redirection = false;
+            }
+            Kind::Word if for_clause == 1 => {
+                styles[span].fill(Style::Variable);
+                for_clause = 2;
+            }
+            Kind::Word if for_clause == 2 && token.text == "in" => {
+                styles[span].fill(Style::Keyword);
+                for_clause = 0;
+            }
+            Kind::Word if command && lexer::RESERVED_WORDS.contains(&token.text.as_str()) => {
+                styles[span].fill(Style::Keyword);
+                command = lexer::COMMAND_PREFIXES.contains(&token.text.as_str());
+                if token.text == "for" {
+                    for_clause = 1;
+                }
+            }
+            Kind::Word if command && lexer::is_assignment(&token.text) => {
+                let name = token.text.find('=').unwrap_or(0);
+                styles[span.start..span.start + name].fill(Style::Variable);
+                word(&mut styles, bytes, span.start + name + 1..span.end);
+            }
+            Kind::Word if command => {
+                let definition = tokens
+                    .get(index + 1)
+                    .is_some_and(|next| next.kind == Kind::Operator && next.text == "(");
+                let style = match definition {
+                    true => Style::Function,
+                    false => command_style(shell, &token.text),
+                };
+                styles[span.clone()].fill(style);
+                if !token.terminated {
+                    word(&mut styles, bytes, span);
+                }
+                command = false;
+            }
+            Kind::Word => {
+                word(&mut styles, bytes, span);
+                for_clause = 0;
+            }
+        }
+    }
+
+    if let Err(Error::Unexpected(token) | Error::Unsupported(token)) =
+        parser::parse(line, &shell.aliases)
+        && line.get(token.span.clone()) == Some(token.text.as_str())
+    {
+        styles[token.span].fill(Style::Error);
+    }
+
+    line.char_indices().map(|(i, _)| styles[i]).collect()
+}
+
+/// Styles the quotes and expansions of the word in `span`.
+fn word(styles: &mut [Style], bytes: &[u8], span: Range<usize>) {
+    let mut i = span.start;
+    while i < span.end {
+        let (end, style) = match bytes[i] {
+            b'\\' => (i + 2, Style::Plain),
+            b'\'' => (
+                lexer::scan_single_quotes(bytes, i).unwrap_or(0),
+                Style::String,
+            ),
+            b'"' => (
+                lexer::scan_double_quotes(bytes, i).unwrap_or(0),
+                Style::String,
+            ),
+            b'$' | b'`' => (expansion(bytes, i).unwrap_or(0), Style::Variable),
+            _ => (i + 1, Style::Plain),
+        };
+        // The input ends inside the quote or substitution.
+        if end == 0 {
+            styles[i..span.end].fill(Style::Error);
+            return;
+        }
+
+        let end = end.min(span.end);
+        styles[i..end].fill(style);
+        if bytes[i] == b'"' {
+            let mut j = i + 1;
+            while j < end {
+                match bytes[j] {
+                    b'\\' => j += 2,
+                    b'$' | b'`' => {
+                        let expansion_end = expansion(bytes, j).unwrap_or(end).min(end);
+                        styles[j..expansion_end].fill(Style::Variable);
+                        j = expansion_end;
+                    }
+                    _ => j += 1,
+                }
+            }
+        }
+        i = end;
+    }
+}
+
+/// Returns the end of the parameter expansion or command substitution at `start`, or `None` if
+/// it is not terminated.
+fn expansion(bytes: &[u8], start: usize) -> Option<usize> {
+    if bytes[start] == b'`' {
+        return lexer::scan_backquotes(bytes, start);
+    }
+    match bytes.get(start + 1) {
+        Some(b'(' | b'{') => lexer::scan_dollar(bytes, start),
+        Some(b) if b.is_ascii_alphabetic() || *b == b'_' => Some(
+            start
+                + 1
+                + bytes[start + 1..]
+                    .iter()
+                    .take_while(|b| b.is_ascii_alphanumeric() || **b == b'_')
+                    .count(),
+        ),
+        Some(b) if b"@*#?-$!0123456789".contains(b) => Some(start + 2),
+        _ => Some(start + 1),
+    }
+}
+
+/// Styles a command name by what it resolves to.
+fn command_style(shell: &Shell, word: &str) -> Style {
+    if word.contains(['$', '`']) {
+        return Style::Plain;
+    }
+    if shell.aliases.contains_key(word) {
+        return Style::Alias;
+    }
+
+    let mut name = String::with_capacity(word.len());
+    let mut quote = None;
+    let mut chars = word.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (None, '\'' | '"') => quote = Some(c),
+            (Some(open), _) if open == c => quote = None,
+            (None, '\\') => name.extend(chars.next()),
+            _ => name.push(c),
+        }
+    }
+
+    if shell.functions.contains_key(&name) {
+        Style::Function
+    } else if builtins::find(&name).is_some() {
+        Style::Builtin
+    } else if name.contains('/') {
+        match shell::is_executable(Path::new(&name)) {
+            true => Style::Executable,
+            false => Style::Missing,
+        }
+    } else if shell.find_executable(&name).is_some() {
+        Style::Executable
+    } else {
+        Style::Missing
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shell::Streams;
+
+    /// Writes the style of each character as a letter, so that a line and its styles line up.
+    fn styles(shell: &Shell, line: &str) -> String {
+        highlight(shell, line)
+            .into_iter()
+            .map(|style| match style {
+                Style::Plain => '.',
+                Style::Builtin => 'b',
+                Style::Function => 'f',
+                Style::Alias => 'a',
+                Style::Executable => 'x',
+                Style::Missing => 'm',
+                Style::Keyword => 'k',
+                Style::String => 's',
+                Style::Variable => 'v',
+                Style::Operator => 'o',
+                Style::Redirection => 'r',
+                Style::Comment => 'c',
+                Style::Error => 'e',
+                Style::Suggestion | Style::Selection => '?',
+            })
+            .collect()
+    }
+
+    #[test]
+    fn words_are_styled_by_their_role() {
+        let shell = Shell::for_tests();
+        assert_eq!(
+            styles(&shell, r#"cd 'a' "b$c" | cat 2>out # d"#),
+            r#"bb.sss.ssvvs.o.xxx.rr....ccc"#
+        );
+        assert_eq!(
+            styles(&shell, "x=$y nope ${z:-w} `v`; é"),
+            "v.vv.mmmm.vvvvvvv.vvvo.m"
+        );
+    }
+
+    #[test]
+    fn keywords_are_only_keywords_where_commands_go() {
+        let shell = Shell::for_tests();
+        assert_eq!(
+            styles(&shell, "if true; then cd if; fi"),
+            "kk.bbbbo.kkkk.bb...o.kk"
+        );
+        assert_eq!(
+            styles(&shell, "for do in a b; do :; done"),
+            "kkk.vv.kk....o.kk.bo.kkkk"
+        );
+    }
+
+    #[test]
+    fn command_names_are_looked_up() {
+        let mut shell = Shell::for_tests();
+        shell.aliases.insert("ll".to_owned(), "ls -l".to_owned());
+        shell.execute("g() { :; }", &Streams::default()).unwrap();
+        assert_eq!(styles(&shell, "ll; g; 'g'; cd"), "aao.fo.fffo.bb");
+        assert_eq!(styles(&shell, "h() { :; }"), "foo.k.bo.k");
+        assert_eq!(
+            styles(&shell, "/bin/sh; /nonexistent"),
+            "xxxxxxxo.mmmmmmmmmmmm"
+        );
+        assert_eq!(styles(&shell, "$cmd"), "....");
+    }
+
+    #[test]
+    fn errors_are_marked_where_they_are() {
+        let shell = Shell::for_tests();
+        assert_eq!(styles(&shell, "cd 'a b"), "bb.eeee");
+        assert_eq!(styles(&shell, "cd \"$(a"), "bb.eeee");
+        assert_eq!(styles(&shell, "cd a; fi"), "bb..o.ee");
+        assert_eq!(styles(&shell, "cd )"), "bb.e");
+    }
+}
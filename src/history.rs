@@ -0,0 +1,375 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, Write},
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+};
+
+use crate::variables::Variables;
+
+const DEFAULT_SIZE: usize = 500;
+
+#[derive(Debug)]
+pub struct History {
+    /// The file entries are saved to, or `None` while they are kept in memory only.
+    file: Option<PathBuf>,
+    entries: Vec<String>,
+    /// Number of entries dropped from the front since startup, which keeps the numbers of
+    /// the remaining ones stable.
+    dropped: usize,
+
+    size: usize,
+    ignore_dups: bool,
+    ignore_space: bool,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            file: None,
+            entries: Vec::new(),
+            dropped: 0,
+
+            size: DEFAULT_SIZE,
+            ignore_dups: false,
+            ignore_space: false,
+        }
+    }
+}
+
+impl History {
+    /// Takes the number of entries to keep from `HISTSIZE`, and the lines to leave out from
+    /// `HISTCONTROL`.
+    pub fn configure(&mut self, variables: &Variables) {
+        self.size = variables
+            .get("HISTSIZE")
+            .and_then(|size| size.parse().ok())
+            .unwrap_or(DEFAULT_SIZE);
+
+        self.ignore_dups = false;
+        self.ignore_space = false;
+        for option in variables.get("HISTCONTROL").unwrap_or_default().split(':') {
+            match option {
+                "ignoredups" => self.ignore_dups = true,
+                "ignorespace" => self.ignore_space = true,
+                "ignoreboth" => {
+                    self.ignore_dups = true;
+                    self.ignore_space = true;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Loads the entries saved in `file`, and saves the next ones to it once it could be read.
+    pub fn open(&mut self, file: PathBuf) -> io::Result<()> {
+        self.load(&file)?;
+        self.file = Some(file);
+        Ok(())
+    }
+
+    pub fn entries(&self) -> &[String] {
+        &self.entries
+    }
+
+    /// Returns the rest of the most recent single-line entry that starts with `prefix` and is
+    /// longer than it.
+    pub fn suggestion(&self, prefix: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .rev()
+            .filter_map(|entry| entry.strip_prefix(prefix))
+            .find(|rest| !rest.is_empty() && !rest.contains('\n'))
+    }
+
+    /// Returns the history number of the entry at `index`.
+    pub fn number(&self, index: usize) -> usize {
+        self.dropped + index + 1
+    }
+
+    /// Returns the history number the next entry will get.
+    pub fn next_number(&self) -> usize {
+        self.number(self.entries.len())
+    }
+
+    pub fn push(&mut self, line: &str) -> io::Result<()> {
+        if line.trim().is_empty()
+            || (self.ignore_space && line.starts_with(' '))
+            || (self.ignore_dups && self.entries.last().is_some_and(|last| last == line))
+            || self.size == 0
+        {
+            return Ok(());
+        }
+
+        self.entries.push(line.to_owned());
+        if self.entries.len() > self.size {
+            let excess = self.entries.len() - self.size;
+            self.entries.drain(..excess);
+            self.dropped += excess;
+        }
+
+        // The history stays in memory after it fails to be saved once.
+        if let Some(file) = &self.file
+            && let Err(error) = append(file, line)
+        {
+            let error = io::Error::new(error.kind(), format!("{}: {}", file.display(), error));
+            self.file = None;
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    fn load(&mut self, path: &Path) -> io::Result<()> {
+        let mut file = match OpenOptions::new().read(true).write(true).open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        lock(&file)?;
+
+        let mut content = String::new();
+        file.read_to_string(&mut content)?;
+        let mut entries: Vec<String> = content.lines().map(decode).collect();
+
+        if entries.len() > self.size {
+            entries.drain(..entries.len() - self.size);
+
+            // Truncate in place rather than replacing the file, so that the lock other
+            // sessions wait on stays attached to the same inode.
+            let content: String = entries.iter().map(|entry| encode(entry) + "\n").collect();
+            file.set_len(0)?;
+            file.rewind()?;
+            file.write_all(content.as_bytes())?;
+        }
+
+        self.entries = entries;
+
+        Ok(())
+    }
+}
+
+/// Appends the record of `line` to the history file at `path`.
+fn append(path: &Path, line: &str) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    lock(&file)?;
+
+    // A single write of the whole record keeps appends from concurrent sessions intact.
+    let mut record = encode(line);
+    record.push('\n');
+    file.write_all(record.as_bytes())
+}
+
+fn lock(file: &File) -> io::Result<()> {
+    // The lock is released when the file is closed.
+    unsafe {
+        if libc::flock(file.as_raw_fd(), libc::LOCK_EX) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Escapes newlines so that every entry occupies exactly one line of the history file.
+fn encode(entry: &str) -> String {
+    entry.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+fn decode(record: &str) -> String {
+    let mut entry = String::with_capacity(record.len());
+    let mut chars = record.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                entry.push('\n');
+                chars.next();
+            }
+            ('\\', Some('\\')) => {
+                entry.push('\\');
+                chars.next();
+            }
+            (c, _) => entry.push(c),
+        }
+    }
+    entry
+}
+
+/// History navigation state, restricted to the entries starting with the text typed
+/// before the navigation started.
+#[derive(Debug)]
+pub struct Navigation {
+    prefix: String,
+    index: usize,
+}
+
+impl Navigation {
+    pub fn new(prefix: &str, history: &History) -> Self {
+        Self {
+            prefix: prefix.to_owned(),
+            index: history.entries.len(),
+        }
+    }
+
+    pub fn previous<'a>(&'a mut self, history: &'a History) -> Option<&'a str> {
+        let current = history.entries.get(self.index);
+        let index = history.entries[..self.index]
+            .iter()
+            .rposition(|entry| entry.starts_with(&self.prefix) && Some(entry) != current)?;
+        self.index = index;
+
+        Some(&history.entries[index])
+    }
+
+    pub fn next<'a>(&'a mut self, history: &'a History) -> Option<&'a str> {
+        let current = history.entries.get(self.index)?;
+        match history.entries[self.index + 1..]
+            .iter()
+            .position(|entry| entry.starts_with(&self.prefix) && entry != current)
+        {
+            Some(offset) => {
+                self.index += 1 + offset;
+                Some(&history.entries[self.index])
+            }
+            None => {
+                self.index = history.entries.len();
+                Some(&self.prefix)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+
+    fn configured(settings: &[(&str, &str)]) -> History {
+        let mut variables = Variables::default();
+        for (name, value) in settings {
+            variables.set(name, *value);
+        }
+        let mut history = History::default();
+        history.configure(&variables);
+        history
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("oxide-film-history-{}-{}", name, process::id()))
+    }
+
+    #[test]
+    fn records_keep_entries_on_one_line() {
+        for entry in [
+            "ls",
+            "echo a\necho b",
+            "printf '\\n'",
+            "echo \\\\n",
+            "ends with \\",
+            "\n\n",
+        ] {
+            let record = encode(entry);
+            assert!(!record.contains('\n'), "{:?}", record);
+            assert_eq!(decode(&record), entry);
+        }
+        assert_eq!(encode("a\\nb\nc"), "a\\\\nb\\nc");
+        // A lone backslash is read as itself.
+        assert_eq!(decode("a\\b\\"), "a\\b\\");
+    }
+
+    #[test]
+    fn histcontrol_leaves_out_lines() {
+        let mut history = configured(&[]);
+        for line in ["ls", "ls", " secret", "", "  "] {
+            history.push(line).unwrap();
+        }
+        assert_eq!(history.entries(), ["ls", "ls", " secret"]);
+
+        let mut history = configured(&[("HISTCONTROL", "ignoredups")]);
+        for line in ["ls", "ls", "pwd", "ls", " ls", " ls"] {
+            history.push(line).unwrap();
+        }
+        assert_eq!(history.entries(), ["ls", "pwd", "ls", " ls"]);
+
+        let mut history = configured(&[("HISTCONTROL", "ignorespace")]);
+        for line in ["ls", "ls", " secret"] {
+            history.push(line).unwrap();
+        }
+        assert_eq!(history.entries(), ["ls", "ls"]);
+
+        let mut history = configured(&[("HISTCONTROL", "ignoreboth")]);
+        for line in ["ls", "ls", " secret", "pwd"] {
+            history.push(line).unwrap();
+        }
+        assert_eq!(history.entries(), ["ls", "pwd"]);
+
+        let mut history = configured(&[("HISTCONTROL", "ignorespace:ignoredups")]);
+        for line in ["ls", "ls", " secret"] {
+            history.push(line).unwrap();
+        }
+        assert_eq!(history.entries(), ["ls"]);
+    }
+
+    #[test]
+    fn histsize_bounds_the_entries_and_the_file() {
+        let path = temp_path("size");
+        fs::write(&path, "one\ntwo\nthree\\nlines\nfour\n").unwrap();
+        let mut history = configured(&[("HISTSIZE", "2")]);
+        let opened = history.open(path.clone());
+        let truncated = fs::read_to_string(&path);
+
+        let pushed = history.push("five");
+        let appended = fs::read_to_string(&path);
+        fs::remove_file(&path).unwrap();
+
+        opened.unwrap();
+        assert_eq!(truncated.unwrap(), "three\\nlines\nfour\n");
+        pushed.unwrap();
+        assert_eq!(history.entries(), ["four", "five"]);
+        assert_eq!(appended.unwrap(), "three\\nlines\nfour\nfive\n");
+        // Entries keep their numbers as older ones are dropped.
+        assert_eq!(history.number(0), 2);
+        assert_eq!(history.next_number(), 4);
+
+        let mut history = configured(&[("HISTSIZE", "0")]);
+        history.push("ls").unwrap();
+        assert!(history.entries().is_empty());
+    }
+
+    #[test]
+    fn a_history_that_cannot_be_saved_stays_in_memory() {
+        let mut history = History {
+            file: Some(PathBuf::from("/dev/null/history")),
+            ..History::default()
+        };
+        let error = history.push("ls").unwrap_err();
+        assert!(error.to_string().starts_with("/dev/null/history: "));
+        history.push("pwd").unwrap();
+        assert_eq!(history.entries(), ["ls", "pwd"]);
+    }
+
+    #[test]
+    fn navigation_goes_through_the_entries_starting_with_the_prefix() {
+        let mut history = History::default();
+        for line in ["git log", "ls", "git status", "git status", "gitk"] {
+            history.push(line).unwrap();
+        }
+
+        let mut navigation = Navigation::new("git ", &history);
+        assert_eq!(navigation.previous(&history), Some("git status"));
+        // The same entry is not offered twice in a row.
+        assert_eq!(navigation.previous(&history), Some("git log"));
+        assert_eq!(navigation.previous(&history), None);
+        assert_eq!(navigation.next(&history), Some("git status"));
+        // Past the last entry comes back the prefix.
+        assert_eq!(navigation.next(&history), Some("git "));
+        assert_eq!(navigation.next(&history), None);
+
+        let mut navigation = Navigation::new("", &history);
+        assert_eq!(navigation.previous(&history), Some("gitk"));
+        assert_eq!(navigation.previous(&history), Some("git status"));
+        assert_eq!(navigation.previous(&history), Some("ls"));
+    }
+}
@@ -0,0 +1,376 @@
+use std::{fs, io, path::Path};
+
+use oxide_film::event::{Key, Modifiers};
+
+/// What the line editor does when a key bound to it is typed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Runs the line, or starts a new one when the command is incomplete.
+    AcceptLine,
+    InsertNewline,
+    /// Exits the shell.
+    Exit,
+    /// Drops the line and starts over with a new prompt.
+    SendBreak,
+    /// Leaves the search or the completion menu, or drops the selection.
+    Abort,
+    /// Completes the word at the cursor, or selects the next candidate of the menu.
+    Complete,
+    /// Selects the previous candidate of the menu, or completes like `Complete`.
+    CompleteBackward,
+    /// Scrolls the completion menu a page up, moving the selection along.
+    MenuPageUp,
+    MenuPageDown,
+    /// Scrolls the completion menu a row up, as the mouse wheel does.
+    MenuScrollUp,
+    MenuScrollDown,
+    SearchBackward,
+    SearchForward,
+    /// Moves left a character.
+    BackwardChar,
+    /// Moves right a character, or takes the suggestion at the end of the line.
+    ForwardChar,
+    /// Moves to the end of the word, or takes the next word of the suggestion.
+    ForwardWord,
+    BeginningOfLine,
+    /// Moves to the end of the line, or takes the suggestion there.
+    EndOfLine,
+    /// Moves up a line of the input, or to the previous entry of the history from the first
+    /// one.
+    UpLineOrHistory,
+    /// Moves down a line of the input, or to the next entry of the history from the last one.
+    DownLineOrHistory,
+    BackwardDeleteChar,
+    /// Cuts the selection to the kill ring and the clipboard.
+    KillRegion,
+    /// Copies the selection, or the last cut text when nothing is selected, to the kill ring
+    /// and the clipboard.
+    CopyRegionAsKill,
+    /// Inserts the last text of the kill ring.
+    Yank,
+    /// Asks the terminal for the clipboard, which it sends back if it allows it to be read.
+    PasteClipboard,
+}
+
+pub const ACTIONS: &[(&str, Action)] = &[
+    ("abort", Action::Abort),
+    ("accept-line", Action::AcceptLine),
+    ("backward-char", Action::BackwardChar),
+    ("backward-delete-char", Action::BackwardDeleteChar),
+    ("beginning-of-line", Action::BeginningOfLine),
+    ("complete", Action::Complete),
+    ("complete-backward", Action::CompleteBackward),
+    ("copy-region-as-kill", Action::CopyRegionAsKill),
+    ("down-line-or-history", Action::DownLineOrHistory),
+    ("end-of-line", Action::EndOfLine),
+    ("exit", Action::Exit),
+    ("forward-char", Action::ForwardChar),
+    ("forward-word", Action::ForwardWord),
+    ("insert-newline", Action::InsertNewline),
+    ("kill-region", Action::KillRegion),
+    ("menu-page-down", Action::MenuPageDown),
+    ("menu-page-up", Action::MenuPageUp),
+    ("menu-scroll-down", Action::MenuScrollDown),
+    ("menu-scroll-up", Action::MenuScrollUp),
+    ("paste-clipboard", Action::PasteClipboard),
+    ("search-backward", Action::SearchBackward),
+    ("search-forward", Action::SearchForward),
+    ("send-break", Action::SendBreak),
+    ("up-line-or-history", Action::UpLineOrHistory),
+    ("yank", Action::Yank),
+];
+
+impl Action {
+    pub fn find(name: &str) -> Option<Self> {
+        ACTIONS
+            .iter()
+            .find(|(action, _)| *action == name)
+            .map(|(_, action)| *action)
+    }
+
+    pub fn name(self) -> &'static str {
+        ACTIONS
+            .iter()
+            .find(|(_, action)| *action == self)
+            .map_or("", |(name, _)| name)
+    }
+}
+
+/// The keys bound to actions of the line editor. A key typed without modifiers or with Shift
+/// that is not bound inserts its character.
+#[derive(Debug)]
+pub struct Keymap {
+    bindings: Vec<(Key, Modifiers, Action)>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        const DEFAULT: &[(&str, Action)] = &[
+            ("enter", Action::AcceptLine),
+            ("shift-enter", Action::AcceptLine),
+            ("alt-enter", Action::InsertNewline),
+            ("ctrl-enter", Action::InsertNewline),
+            ("ctrl-d", Action::Exit),
+            ("ctrl-c", Action::SendBreak),
+            ("esc", Action::Abort),
+            ("ctrl-g", Action::Abort),
+            ("tab", Action::Complete),
+            ("shift-tab", Action::CompleteBackward),
+            ("backtab", Action::CompleteBackward),
+            ("shift-backtab", Action::CompleteBackward),
+            ("pageup", Action::MenuPageUp),
+            ("pagedown", Action::MenuPageDown),
+            ("ctrl-r", Action::SearchBackward),
+            ("ctrl-s", Action::SearchForward),
+            ("left", Action::BackwardChar),
+            ("right", Action::ForwardChar),
+            ("alt-right", Action::ForwardWord),
+            ("ctrl-right", Action::ForwardWord),
+            ("home", Action::BeginningOfLine),
+            ("end", Action::EndOfLine),
+            ("up", Action::UpLineOrHistory),
+            ("down", Action::DownLineOrHistory),
+            ("backspace", Action::BackwardDeleteChar),
+            ("ctrl-w", Action::KillRegion),
+            ("alt-w", Action::CopyRegionAsKill),
+            ("ctrl-y", Action::Yank),
+            ("alt-y", Action::PasteClipboard),
+        ];
+
+        let mut keymap = Self {
+            bindings: Vec::new(),
+        };
+        for (key, action) in DEFAULT {
+            let (key, modifiers) = parse_key(key).unwrap();
+            keymap.bind(key, modifiers, *action);
+        }
+        keymap
+    }
+}
+
+impl Keymap {
+    pub fn get(&self, key: &Key, modifiers: Modifiers) -> Option<Action> {
+        self.bindings
+            .iter()
+            .find(|binding| binding.0 == *key && binding.1 == modifiers)
+            .map(|binding| binding.2)
+    }
+
+    pub fn bind(&mut self, key: Key, modifiers: Modifiers, action: Action) {
+        self.unbind(&key, modifiers);
+        self.bindings.push((key, modifiers, action));
+    }
+
+    /// Removes the binding of a key, returning whether it was bound.
+    pub fn unbind(&mut self, key: &Key, modifiers: Modifiers) -> bool {
+        let len = self.bindings.len();
+        self.bindings
+            .retain(|binding| binding.0 != *key || binding.1 != modifiers);
+        self.bindings.len() < len
+    }
+
+    pub fn bindings(&self) -> impl Iterator<Item = (String, Action)> {
+        self.bindings
+            .iter()
+            .map(|(key, modifiers, action)| (key_name(key, *modifiers), *action))
+    }
+
+    /// Binds the keys of a keymap file, which has a key and the name of an action on each
+    /// line, and comments on lines starting with `#`. Lines that are not bindings are skipped,
+    /// and returned with their number and what is wrong with them.
+    pub fn load(&mut self, path: &Path) -> io::Result<Vec<(usize, String)>> {
+        let source = fs::read_to_string(path)?;
+        let mut errors = Vec::new();
+        for (number, line) in source.lines().enumerate() {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let error = match words.as_slice() {
+                [] => continue,
+                [comment, ..] if comment.starts_with('#') => continue,
+                &[key, action] => match (parse_key(key), Action::find(action)) {
+                    (Some((key, modifiers)), Some(action)) => {
+                        self.bind(key, modifiers, action);
+                        continue;
+                    }
+                    (None, _) => format!("{}: unknown key", key),
+                    (_, None) => format!("{}: unknown action", action),
+                },
+                _ => "expected a key and an action".to_owned(),
+            };
+            errors.push((number + 1, error));
+        }
+        Ok(errors)
+    }
+}
+
+/// The modifiers in the order they are written before a key.
+const MODIFIERS: &[(&str, Modifiers)] = &[
+    ("ctrl", Modifiers::CTRL),
+    ("alt", Modifiers::ALT),
+    ("shift", Modifiers::SHIFT),
+    ("super", Modifiers::SUPER),
+    ("hyper", Modifiers::HYPER),
+    ("meta", Modifiers::META),
+];
+
+/// The keys that are written by name rather than as their character.
+const KEYS: &[(&str, Key)] = &[
+    ("enter", Key::Character('\n')),
+    ("tab", Key::Character('\t')),
+    ("space", Key::Character(' ')),
+    ("esc", Key::Esc),
+    ("backspace", Key::Backspace),
+    ("null", Key::Null),
+    ("left", Key::Left),
+    ("right", Key::Right),
+    ("up", Key::Up),
+    ("down", Key::Down),
+    ("home", Key::Home),
+    ("end", Key::End),
+    ("pageup", Key::PageUp),
+    ("pagedown", Key::PageDown),
+    ("backtab", Key::BackTab),
+    ("insert", Key::Insert),
+    ("delete", Key::Delete),
+];
+
+/// Parses a key written as its name or character, after modifiers each followed by `-`, as
+/// in `ctrl-alt-right` or `alt-.`.
+pub fn parse_key(spec: &str) -> Option<(Key, Modifiers)> {
+    let mut modifiers = Modifiers::NONE;
+    let mut rest = spec;
+    'modifiers: loop {
+        for (name, modifier) in MODIFIERS {
+            if let Some(after) = rest
+                .strip_prefix(name)
+                .and_then(|after| after.strip_prefix('-'))
+                .filter(|after| !after.is_empty())
+            {
+                modifiers = modifiers | *modifier;
+                rest = after;
+                continue 'modifiers;
+            }
+        }
+        break;
+    }
+
+    if let Some((_, key)) = KEYS.iter().find(|(name, _)| *name == rest) {
+        return Some((key.clone(), modifiers));
+    }
+    if let Some(number) = rest.strip_prefix('f').and_then(|n| n.parse().ok())
+        && (1..=35).contains(&number)
+    {
+        return Some((Key::F(number), modifiers));
+    }
+    let mut chars = rest.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some((Key::Character(c), modifiers)),
+        _ => None,
+    }
+}
+
+/// Writes a key the way `parse_key` reads it.
+pub fn key_name(key: &Key, modifiers: Modifiers) -> String {
+    let mut name: String = MODIFIERS
+        .iter()
+        .filter(|(_, modifier)| modifiers.contains(*modifier))
+        .map(|(name, _)| format!("{}-", name))
+        .collect();
+    match (KEYS.iter().find(|(_, named)| named == key), key) {
+        (Some((key, _)), _) => name.push_str(key),
+        (None, Key::F(n)) => name.push_str(&format!("f{}", n)),
+        (None, Key::Character(c)) => name.push(*c),
+        (None, _) => {}
+    }
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    #[test]
+    fn keys_are_written_the_way_they_are_read() {
+        assert_eq!(
+            parse_key("ctrl-alt-right"),
+            Some((Key::Right, Modifiers::CTRL | Modifiers::ALT))
+        );
+        assert_eq!(
+            parse_key("alt-."),
+            Some((Key::Character('.'), Modifiers::ALT))
+        );
+        assert_eq!(parse_key("-"), Some((Key::Character('-'), Modifiers::NONE)));
+        assert_eq!(
+            parse_key("ctrl--"),
+            Some((Key::Character('-'), Modifiers::CTRL))
+        );
+        assert_eq!(parse_key("shift-f12"), Some((Key::F(12), Modifiers::SHIFT)));
+        assert_eq!(parse_key("é"), Some((Key::Character('é'), Modifiers::NONE)));
+        for spec in ["ctrl-", "f36", "f0", "ctrl-nope", "hyper", "right-ctrl"] {
+            assert_eq!(parse_key(spec), None, "{:?}", spec);
+        }
+
+        for spec in [
+            "ctrl-alt-shift-super-hyper-meta-f35",
+            "enter",
+            "alt-enter",
+            "space",
+            "x",
+        ] {
+            let (key, modifiers) = parse_key(spec).unwrap();
+            assert_eq!(key_name(&key, modifiers), spec);
+        }
+        // Modifiers come out in their usual order.
+        let (key, modifiers) = parse_key("alt-ctrl-y").unwrap();
+        assert_eq!(key_name(&key, modifiers), "ctrl-alt-y");
+    }
+
+    #[test]
+    fn every_action_has_a_name() {
+        let mut keymap = Keymap::default();
+        for (name, action) in ACTIONS {
+            assert_eq!(Action::find(name), Some(*action));
+            assert_eq!(action.name(), *name);
+        }
+        for (key, action) in keymap.bindings().collect::<Vec<_>>() {
+            let (key, modifiers) = parse_key(&key).unwrap();
+            assert_eq!(keymap.get(&key, modifiers), Some(action));
+            assert!(keymap.unbind(&key, modifiers));
+        }
+        assert_eq!(keymap.bindings().count(), 0);
+    }
+
+    #[test]
+    fn keymap_files_bind_what_they_can() {
+        let path = env::temp_dir().join(format!("oxide-film-keymap-{}", std::process::id()));
+        fs::write(
+            &path,
+            "# comment\n\nctrl-x yank\nenter insert-newline\nctrl-nope yank\nctrl-y nothing\ntab\n",
+        )
+        .unwrap();
+        let mut keymap = Keymap::default();
+        let loaded = keymap.load(&path);
+        fs::write(&path, b"ctrl-x \xff\n").unwrap();
+        let invalid = keymap.load(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            loaded.unwrap(),
+            [
+                (5, String::from("ctrl-nope: unknown key")),
+                (6, String::from("nothing: unknown action")),
+                (7, String::from("expected a key and an action")),
+            ]
+        );
+        let key = |spec| parse_key(spec).unwrap();
+        let get = |(key, modifiers)| keymap.get(&key, modifiers);
+        assert_eq!(get(key("ctrl-x")), Some(Action::Yank));
+        assert_eq!(get(key("enter")), Some(Action::InsertNewline));
+        // The bindings on the lines that are not are left alone.
+        assert_eq!(get(key("ctrl-y")), Some(Action::Yank));
+        assert_eq!(get(key("tab")), Some(Action::Complete));
+
+        assert_eq!(invalid.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
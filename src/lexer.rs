@@ -0,0 +1,319 @@
+use std::ops::Range;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Word,
+    Operator,
+    Redirection,
+    Comment,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: Kind,
+    pub span: Range<usize>,
+    pub text: String,
+    /// Whether every quote and substitution opened inside the token is closed.
+    pub terminated: bool,
+}
+
+const OPERATORS: &[&str] = &["&&", "||", ";;", ";", "&", "|", "(", ")", "\n"];
+const REDIRECTIONS: &[&str] = &["<<-", ">>", ">|", ">&", "<>", "<&", "<<", ">", "<"];
+
+pub const RESERVED_WORDS: &[&str] = &[
+    "!", "{", "}", "case", "do", "done", "elif", "else", "esac", "fi", "for", "if", "in", "then",
+    "until", "while",
+];
+
+/// Reserved words after which a command name is expected.
+pub const COMMAND_PREFIXES: &[&str] = &[
+    "!", "{", "do", "elif", "else", "if", "then", "until", "while",
+];
+
+pub fn tokenize(source: &str) -> Vec<Token> {
+    let bytes = source.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    let mut push = |kind, span: Range<usize>, terminated| {
+        tokens.push(Token {
+            kind,
+            text: source[span.clone()].to_owned(),
+            span,
+            terminated,
+        })
+    };
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b' ' | b'\t' => i += 1,
+            b'\\' if bytes.get(i + 1) == Some(&b'\n') => i += 2,
+            b'#' => {
+                let end = source[i..].find('\n').map_or(source.len(), |end| i + end);
+                push(Kind::Comment, i..end, true);
+                i = end;
+            }
+            _ => {
+                if let Some(operator) = OPERATORS.iter().find(|op| source[i..].starts_with(*op)) {
+                    push(Kind::Operator, i..i + operator.len(), true);
+                    i += operator.len();
+                    continue;
+                }
+
+                let digits = bytes[i..].iter().take_while(|b| b.is_ascii_digit()).count();
+                if let Some(redirection) = REDIRECTIONS
+                    .iter()
+                    .find(|op| source[i + digits..].starts_with(*op))
+                {
+                    let end = i + digits + redirection.len();
+                    push(Kind::Redirection, i..end, true);
+                    i = end;
+                    continue;
+                }
+
+                let (end, terminated) = match scan_word(bytes, i) {
+                    Some(end) => (end, true),
+                    None => (bytes.len(), false),
+                };
+                push(Kind::Word, i..end, terminated);
+                i = end;
+            }
+        }
+    }
+
+    tokens
+}
+
+/// Returns the end of the word starting at `start`, or `None` if the input ends inside a
+/// quote or a substitution.
+pub fn scan_word(bytes: &[u8], start: usize) -> Option<usize> {
+    let mut i = start;
+    while i < bytes.len() {
+        i = match bytes[i] {
+            b' ' | b'\t' | b'\n' | b';' | b'&' | b'|' | b'(' | b')' | b'<' | b'>' => break,
+            b'\\' if i + 1 < bytes.len() => i + 2,
+            b'\\' => return None,
+            b'\'' => scan_single_quotes(bytes, i)?,
+            b'"' => scan_double_quotes(bytes, i)?,
+            b'`' => scan_backquotes(bytes, i)?,
+            b'$' => scan_dollar(bytes, i)?,
+            _ => i + 1,
+        };
+    }
+    Some(i)
+}
+
+/// Returns the end of the `'...'` string starting at `start`.
+pub fn scan_single_quotes(bytes: &[u8], start: usize) -> Option<usize> {
+    let end = bytes[start + 1..].iter().position(|&b| b == b'\'')?;
+    Some(start + 1 + end + 1)
+}
+
+/// Returns the end of the `"..."` string starting at `start`.
+pub fn scan_double_quotes(bytes: &[u8], start: usize) -> Option<usize> {
+    let mut i = start + 1;
+    loop {
+        i = match bytes.get(i)? {
+            b'"' => return Some(i + 1),
+            b'\\' => i + 2,
+            b'`' => scan_backquotes(bytes, i)?,
+            b'$' => scan_dollar(bytes, i)?,
+            _ => i + 1,
+        };
+    }
+}
+
+/// Returns the end of the `` `...` `` command substitution starting at `start`.
+pub fn scan_backquotes(bytes: &[u8], start: usize) -> Option<usize> {
+    let mut i = start + 1;
+    loop {
+        i = match bytes.get(i)? {
+            b'`' => return Some(i + 1),
+            b'\\' => i + 2,
+            _ => i + 1,
+        };
+    }
+}
+
+/// Returns the end of the expansion introduced by the `$` at `start`.
+pub fn scan_dollar(bytes: &[u8], start: usize) -> Option<usize> {
+    match bytes.get(start + 1) {
+        Some(b'(') => scan_parentheses(bytes, start + 1),
+        Some(b'{') => scan_braces(bytes, start + 1),
+        _ => Some(start + 1),
+    }
+}
+
+fn scan_parentheses(bytes: &[u8], start: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut i = start;
+    loop {
+        i = match bytes.get(i)? {
+            b'(' => {
+                depth += 1;
+                i + 1
+            }
+            b')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i + 1);
+                }
+                i + 1
+            }
+            b'\\' => i + 2,
+            b'\'' => scan_single_quotes(bytes, i)?,
+            b'"' => scan_double_quotes(bytes, i)?,
+            b'`' => scan_backquotes(bytes, i)?,
+            b'$' => scan_dollar(bytes, i)?,
+            _ => i + 1,
+        };
+    }
+}
+
+fn scan_braces(bytes: &[u8], start: usize) -> Option<usize> {
+    let mut i = start + 1;
+    loop {
+        i = match bytes.get(i)? {
+            b'}' => return Some(i + 1),
+            b'\\' => i + 2,
+            b'\'' => scan_single_quotes(bytes, i)?,
+            b'"' => scan_double_quotes(bytes, i)?,
+            b'`' => scan_backquotes(bytes, i)?,
+            b'$' => scan_dollar(bytes, i)?,
+            _ => i + 1,
+        };
+    }
+}
+
+/// Whether `word` is a valid variable name.
+pub fn is_name(word: &str) -> bool {
+    word.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && word.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Whether `word` has the `name=value` form of a variable assignment.
+pub fn is_assignment(word: &str) -> bool {
+    word.split_once('=').is_some_and(|(name, _)| is_name(name))
+}
+
+/// Quotes `word` so that the shell reads it back as a single literal word.
+pub fn quote(word: &str) -> String {
+    if !word.is_empty()
+        && word
+            .chars()
+            .all(|c| c.is_alphanumeric() || "_-./:=@%+,".contains(c))
+    {
+        word.to_owned()
+    } else {
+        format!("'{}'", word.replace('\'', "'\\''"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(source: &str) -> Vec<(Kind, &str)> {
+        tokenize(source)
+            .into_iter()
+            .map(|token| (token.kind, &source[token.span]))
+            .collect()
+    }
+
+    #[test]
+    fn operators_split_words() {
+        assert_eq!(
+            kinds("a&&b|c;d &\n(e)"),
+            [
+                (Kind::Word, "a"),
+                (Kind::Operator, "&&"),
+                (Kind::Word, "b"),
+                (Kind::Operator, "|"),
+                (Kind::Word, "c"),
+                (Kind::Operator, ";"),
+                (Kind::Word, "d"),
+                (Kind::Operator, "&"),
+                (Kind::Operator, "\n"),
+                (Kind::Operator, "("),
+                (Kind::Word, "e"),
+                (Kind::Operator, ")"),
+            ]
+        );
+    }
+
+    #[test]
+    fn redirections_take_their_descriptor() {
+        assert_eq!(
+            kinds("cat <in 2>>log >&2 x2>y 12"),
+            [
+                (Kind::Word, "cat"),
+                (Kind::Redirection, "<"),
+                (Kind::Word, "in"),
+                (Kind::Redirection, "2>>"),
+                (Kind::Word, "log"),
+                (Kind::Redirection, ">&"),
+                (Kind::Word, "2"),
+                (Kind::Word, "x2"),
+                (Kind::Redirection, ">"),
+                (Kind::Word, "y"),
+                (Kind::Word, "12"),
+            ]
+        );
+    }
+
+    #[test]
+    fn quotes_and_substitutions_stay_in_the_word() {
+        assert_eq!(
+            kinds(r#"echo 'a b'"c;d"\ e $(f | g) ${h:-i j} `k l`"#),
+            [
+                (Kind::Word, "echo"),
+                (Kind::Word, r#"'a b'"c;d"\ e"#),
+                (Kind::Word, "$(f | g)"),
+                (Kind::Word, "${h:-i j}"),
+                (Kind::Word, "`k l`"),
+            ]
+        );
+        assert_eq!(
+            kinds("echo a\\\nb # c; d\ne"),
+            [
+                (Kind::Word, "echo"),
+                (Kind::Word, "a\\\nb"),
+                (Kind::Comment, "# c; d"),
+                (Kind::Operator, "\n"),
+                (Kind::Word, "e"),
+            ]
+        );
+    }
+
+    #[test]
+    fn unterminated_words_run_to_the_end() {
+        for source in [
+            "echo 'a; b",
+            "echo \"$(a\nb",
+            "echo ${a",
+            "echo `a",
+            "echo a\\",
+        ] {
+            let tokens = tokenize(source);
+            let last = tokens.last().unwrap();
+            assert_eq!(last.span.end, source.len(), "{:?}", source);
+            assert!(!last.terminated, "{:?}", source);
+        }
+        assert!(tokenize("echo 'a; b'").iter().all(|token| token.terminated));
+    }
+
+    #[test]
+    fn quoted_words_read_back_as_themselves() {
+        assert_eq!(quote("plain/path-1.txt"), "plain/path-1.txt");
+        assert_eq!(quote(""), "''");
+        assert_eq!(quote("a b"), "'a b'");
+        assert_eq!(quote("it's"), r"'it'\''s'");
+        assert_eq!(kinds(&quote("a;b 'c' $d")).len(), 1);
+
+        assert!(is_assignment("_a1=b=c"));
+        assert!(is_assignment("a="));
+        assert!(!is_assignment("1a=b"));
+        assert!(!is_assignment("=b"));
+        assert!(!is_assignment("a"));
+    }
+}
@@ -1,34 +1,95 @@
 #![feature(ascii_char)]
 
 use std::{
+    cmp::Ordering,
+    fs::File,
     io::{self, Read, Write},
-    os::fd::AsRawFd,
+    iter, mem,
+    ops::Range,
+    os::fd::{AsFd, AsRawFd},
+    path::PathBuf,
+    process,
+    time::{Duration, Instant},
 };
 
-use event::{Event, Events, Key};
+use args::Args;
+use clap::Parser;
+use config::Config;
+use highlight::{Style, highlight};
+use history::Navigation;
+use keymap::Action;
+use menu::Menu;
+use prompt::{Prompt, Segments};
+use search::Search;
+use shell::{Shell, Streams};
+use terminal::Terminal;
 
-mod event;
+use oxide_film::event::{
+    Clipboard, DEFAULT_ESCAPE_DELAY, Event, Events, EventsIter, Key, KeyKind, Modifiers, Mouse,
+    MouseButton, MouseTracking, Ready, Response,
+};
 
-fn get_column(stdin: &io::Stdin, stdout: &mut &io::Stdout) -> io::Result<usize> {
-    write!(stdout, "\x1b[6n")?;
-    stdout.flush()?;
+mod args;
+mod builtins;
+mod complete;
+mod config;
+mod expand;
+mod highlight;
+mod history;
+mod keymap;
+mod lexer;
+mod menu;
+mod parser;
+mod pattern;
+mod prompt;
+mod search;
+mod shell;
+mod terminal;
+mod variables;
+mod width;
+
+/// The events read from the terminal, which answers queries through them too.
+type TerminalEvents<'a> = EventsIter<&'a File>;
+
+/// Returns the row and column of the cursor, counted from 1.
+fn get_position(
+    events: &mut TerminalEvents,
+    stdout: &mut &io::Stdout,
+) -> io::Result<(usize, usize)> {
+    events.query(stdout, "\x1b[6n", |response| match response {
+        Response::CursorPosition(row, column) => Some((row.into(), column.into())),
+        _ => None,
+    })
+}
+
+/// How soon a second click at the same place makes a double click.
+const DOUBLE_CLICK: Duration = Duration::from_millis(400);
+
+/// The enhancements of the kitty keyboard protocol asked for while editing: disambiguated
+/// escape codes, the kinds of key events, and shifted and base layout keys.
+const KEYBOARD_FLAGS: u8 = 0b111;
 
-    let column: Vec<u8> = stdin
-        .bytes()
-        .map_while(|byte| byte.ok())
-        .skip_while(|byte| *byte != b';')
-        .skip(1)
-        .take_while(|byte| *byte != b'R')
-        .collect();
-    let column: String = String::from_utf8(column).unwrap();
-    let column: usize = column.parse().unwrap();
-
-    Ok(column)
+/// Returns whether the terminal supports the kitty keyboard protocol.
+///
+/// The query for its flags is followed by one for the primary device attributes, which every
+/// terminal answers, so that a terminal ignoring the first one is not waited on.
+fn query_keyboard_protocol(
+    events: &mut TerminalEvents,
+    stdout: &mut &io::Stdout,
+) -> io::Result<bool> {
+    let mut supported = false;
+    events.query(stdout, "\x1b[?u\x1b[c", |response| match response {
+        Response::KeyboardFlags(_) => {
+            supported = true;
+            None
+        }
+        Response::PrimaryAttributes(_) => Some(supported),
+        _ => None,
+    })
 }
 
 #[derive(Debug)]
 struct Input<'a> {
-    stdin: &'a io::Stdin,
     stdout: &'a io::Stdout,
 
     start_width: usize,
@@ -36,15 +97,37 @@ struct Input<'a> {
     value: String,
     widths: Vec<usize>,
     position: usize,
-    column: usize,
+    /// Shown after the value, without being part of it.
+    suggestion: String,
+    /// Drawn at the right edge of the terminal while the input leaves room for it.
+    right_prompt: Option<Prompt>,
+    /// Drawn at the start of the lines of the value after the first one.
+    continuation: Prompt,
+    /// The width of the terminal.
+    columns: usize,
+    /// The height of the terminal.
+    rows: usize,
+    /// The screen row of the first row of the input, counted from 1 like mouse positions.
+    top: usize,
+    /// The other end of the selection, the cursor being one end.
+    mark: Option<usize>,
+
+    /// The characters on the screen with their styles, which rendering rewrites from the first
+    /// one that changed.
+    drawn: Vec<(char, Style)>,
+    /// The row of the terminal cursor, counted from the first row of the input, and its
+    /// column, which rendering moves to the cursor of the input.
+    cursor: (usize, usize),
+    /// The last row of the input on the screen.
+    last_row: usize,
+    right_prompt_drawn: bool,
 }
 
 impl<'a> Input<'a> {
-    fn new(stdin: &'a io::Stdin, stdout: &'a mut io::Stdout) -> io::Result<Self> {
-        let start_width: usize = get_column(stdin, &mut &*stdout)?;
+    fn new(events: &mut TerminalEvents, mut stdout: &'a io::Stdout) -> io::Result<Self> {
+        let (top, start_width) = get_position(events, &mut stdout)?;
 
         Ok(Self {
-            stdin,
             stdout,
 
             start_width,
@@ -52,62 +135,725 @@ impl<'a> Input<'a> {
             value: String::new(),
             widths: Vec::new(),
             position: 0,
-            column: start_width,
+            suggestion: String::new(),
+            right_prompt: None,
+            continuation: Prompt::default(),
+            columns: usize::MAX,
+            rows: usize::MAX,
+            top,
+            mark: None,
+
+            drawn: Vec::new(),
+            cursor: (0, start_width),
+            last_row: 0,
+            right_prompt_drawn: false,
         })
     }
 
+    fn byte_offset(&self) -> usize {
+        self.offset(self.position)
+    }
+
+    /// Returns the byte offset of the character at `position`.
+    fn offset(&self, position: usize) -> usize {
+        self.value
+            .char_indices()
+            .nth(position)
+            .map_or(self.value.len(), |(offset, _)| offset)
+    }
+
+    /// Returns the positions of the selected characters, unless nothing is selected.
+    fn selection(&self) -> Option<Range<usize>> {
+        let mark = self.mark?;
+        let range = mark.min(self.position)..mark.max(self.position);
+        (!range.is_empty()).then_some(range)
+    }
+
+    /// Returns the selected text, leaving it in place.
+    fn selected(&self) -> Option<String> {
+        let range = self.selection()?;
+        Some(self.value[self.offset(range.start)..self.offset(range.end)].to_owned())
+    }
+
+    /// Removes the selected text and returns it.
+    fn cut(&mut self) -> Option<String> {
+        let range = self.selection()?;
+        let text = self.selected()?;
+        self.splice(self.offset(range.start)..self.offset(range.end), "");
+        Some(text)
+    }
+
+    /// Selects the word the cursor is in, or the whitespace between words.
+    fn select_word(&mut self) {
+        let chars: Vec<char> = self.value.chars().collect();
+        let Some(&c) = chars.get(self.position) else {
+            return;
+        };
+        let same = |other: &char| other.is_whitespace() == c.is_whitespace();
+        let start = chars[..self.position]
+            .iter()
+            .rposition(|other| !same(other))
+            .map_or(0, |i| i + 1);
+        let end = chars[self.position..]
+            .iter()
+            .position(|other| !same(other))
+            .map_or(chars.len(), |i| self.position + i);
+        self.mark = Some(start);
+        self.seek(end);
+    }
+
+    /// Returns the position of the character drawn at a screen row and column, or of the end
+    /// of the line of the input the row is in when nothing is drawn there.
+    fn at(&self, row: usize, column: usize) -> usize {
+        let Some(row) = row.checked_sub(self.top) else {
+            return 0;
+        };
+        let cells: Vec<(char, Style)> = self.value.chars().map(|c| (c, Style::Plain)).collect();
+        self.layout(&cells, self.columns)
+            .iter()
+            .rposition(|&position| position <= (row, column))
+            .unwrap_or(0)
+    }
+
     fn push(&mut self, str: &str) {
-        let new_column = get_column(self.stdin, &mut self.stdout).unwrap();
-        let width = new_column.saturating_sub(self.column);
-        self.column = new_column;
-        self.widths.push(width);
-        self.position += 1;
-        self.value.push_str(str);
-    }
-
-    fn backspace(&mut self) -> io::Result<()> {
-        if !self.value.is_empty() {
-            self.value.pop();
-            self.widths.pop();
+        self.mark = None;
+        let offset = self.byte_offset();
+        for c in str.chars() {
+            self.widths.insert(self.position, width::char_width(c));
+            self.position += 1;
+        }
+        self.value.insert_str(offset, str);
+    }
+
+    fn backspace(&mut self) {
+        self.mark = None;
+        if self.position > 0 {
             self.position -= 1;
-            write!(self.stdout, "\x1b[{}D\x1b[K", self.widths.pop().unwrap())?;
-            self.column -= self.widths.pop().unwrap();
+            let offset = self.byte_offset();
+            self.value.remove(offset);
+            self.widths.remove(self.position);
         }
+    }
+
+    fn left(&mut self) {
+        if self.position > 0 {
+            self.position -= 1;
+        }
+    }
+
+    fn right(&mut self) {
+        if self.position < self.widths.len() {
+            self.position += 1;
+        }
+    }
+
+    /// Replaces the whole input with `value`, leaving the cursor at its end.
+    fn replace(&mut self, value: &str) {
+        self.mark = None;
+        self.value.clear();
+        self.widths.clear();
+        self.position = 0;
+
+        self.push(value)
+    }
+
+    /// Replaces the bytes of the value in `range` with `text`, leaving the cursor after it.
+    fn splice(&mut self, range: Range<usize>, text: &str) {
+        let tail = self.value[range.end..].to_owned();
+        let value = format!("{}{}{}", &self.value[..range.start], text, tail);
+        self.replace(&value);
+        self.seek(self.widths.len() - tail.chars().count())
+    }
+
+    /// Moves the cursor to the character at `position`.
+    fn seek(&mut self, position: usize) {
+        while self.position > position {
+            self.left();
+        }
+        while self.position < position.min(self.widths.len()) {
+            self.right();
+        }
+    }
+
+    /// Moves the cursor to the line of the value above or below, as close to its column as the
+    /// line allows. Returns `false` if there is no such line.
+    fn vertical(&mut self, down: bool) -> bool {
+        let starts: Vec<usize> = iter::once(0)
+            .chain(
+                self.value
+                    .chars()
+                    .enumerate()
+                    .filter(|(_, c)| *c == '\n')
+                    .map(|(i, _)| i + 1),
+            )
+            .collect();
+        let line = starts
+            .iter()
+            .rposition(|&start| start <= self.position)
+            .unwrap_or(0);
+        let target = match down {
+            true if line + 1 < starts.len() => line + 1,
+            false if line > 0 => line - 1,
+            _ => return false,
+        };
+
+        let column: usize = self.widths[starts[line]..self.position].iter().sum();
+        let end = starts
+            .get(target + 1)
+            .map_or(self.widths.len(), |next| next - 1);
+        let mut position = starts[target];
+        let mut width = 0;
+        while position < end && width + self.widths[position] <= column {
+            width += self.widths[position];
+            position += 1;
+        }
+        self.seek(position);
+        true
+    }
+
+    /// Moves the cursor to the end of the next word.
+    fn word_right(&mut self) {
+        let chars: Vec<char> = self.value.chars().collect();
+        while chars.get(self.position).is_some_and(|c| c.is_whitespace()) {
+            self.right();
+        }
+        while chars.get(self.position).is_some_and(|c| !c.is_whitespace()) {
+            self.right();
+        }
+    }
+
+    /// Accepts the suggestion up to the end of its first word.
+    fn accept_word(&mut self) {
+        let rest = self.suggestion.trim_start();
+        let end = self.suggestion.len() - rest.len()
+            + rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let word = self.suggestion[..end].to_owned();
+        self.push(&word);
+        self.suggestion.drain(..end);
+    }
+
+    /// Returns the row, counted from the first row of the input, and the column of each of
+    /// `cells` and of their end on a terminal `columns` wide.
+    fn layout(&self, cells: &[(char, Style)], columns: usize) -> Vec<(usize, usize)> {
+        let columns = columns.max(1);
+        // The prompt takes more rows once the terminal gets narrower than it.
+        let mut row = (self.start_width - 1) / columns;
+        let mut column = (self.start_width - 1) % columns + 1;
+
+        let mut positions = Vec::with_capacity(cells.len() + 1);
+        for &(c, _) in cells {
+            let width = width::char_width(c);
+            if c != '\n' && column + width > columns + 1 {
+                row += 1;
+                column = 1;
+            }
+            positions.push((row, column));
+            match c {
+                '\n' => {
+                    row += 1;
+                    column = self.continuation.width + 1;
+                }
+                _ => column += width,
+            }
+        }
+        if column > columns {
+            row += 1;
+            column = 1;
+        }
+        positions.push((row, column));
+        positions
+    }
+
+    /// Draws the characters changed since the last render with the given styles, followed by
+    /// the suggestion and the right prompt, then moves the terminal cursor to the cursor of the
+    /// input.
+    fn render(&mut self, styles: &[Style]) -> io::Result<()> {
+        let selection = self.selection().unwrap_or_default();
+        let cells: Vec<(char, Style)> = self
+            .value
+            .chars()
+            .zip(styles.iter().copied().chain(iter::repeat(Style::Plain)))
+            .enumerate()
+            .map(|(i, (c, style))| match selection.contains(&i) {
+                true => (c, Style::Selection),
+                false => (c, style),
+            })
+            .chain(self.suggestion.chars().map(|c| (c, Style::Suggestion)))
+            .collect();
+        let unchanged = self
+            .drawn
+            .iter()
+            .zip(&cells)
+            .take_while(|(drawn, cell)| drawn == cell)
+            .count();
+        let positions = self.layout(&cells, self.columns);
+        let end = positions[cells.len()];
+
+        // The right prompt is hidden once the input would reach it, leaving a column between.
+        let right_prompt = self.right_prompt.clone().filter(|prompt| {
+            !prompt.text.contains('\n')
+                && end.0 == 0
+                && end.1.saturating_add(prompt.width) <= self.columns
+        });
+
+        let changed = unchanged < self.drawn.len() || unchanged < cells.len();
+        if changed {
+            self.move_to(positions[unchanged])?;
+            let mut style = None;
+            for (&(c, next), &(_, column)) in cells[unchanged..].iter().zip(&positions[unchanged..])
+            {
+                if c == '\n' {
+                    // A full row leaves the cursor on its last character, which erasing would
+                    // take along.
+                    if column <= self.columns {
+                        write!(self.stdout, "\x1b[K")?;
+                    }
+                    write!(self.stdout, "\x1b[0m\r\n{}", self.continuation.text)?;
+                    style = None;
+                    continue;
+                }
+                if style != Some(next) {
+                    write!(self.stdout, "\x1b[0;{}m", next.sgr())?;
+                    style = Some(next);
+                }
+                // Pasted control characters would move the cursor or start escape sequences.
+                let c = match c {
+                    '\t' => ' ',
+                    c if c.is_control() => char::REPLACEMENT_CHARACTER,
+                    c => c,
+                };
+                write!(self.stdout, "{}", c)?;
+            }
+            write!(self.stdout, "\x1b[0m")?;
+            // The cursor waits on a full row for the next character before wrapping, so a
+            // space wraps it to where the layout puts the end.
+            if cells
+                .last()
+                .is_some_and(|&(c, _)| c != '\n' && end.0 > positions[cells.len() - 1].0)
+            {
+                write!(self.stdout, " \r")?;
+            }
+            write!(self.stdout, "\x1b[J")?;
+            self.cursor = end;
+            self.last_row = end.0;
+            self.drawn = cells;
+            // The screen scrolls up when the input grows past its last row.
+            self.top = self.top.min(self.rows.saturating_sub(self.last_row).max(1));
+        }
+
+        // Rewriting the input erases the right prompt along with the rest of the row.
+        if let Some(prompt) = &right_prompt
+            && (changed || !self.right_prompt_drawn)
+        {
+            self.move_to((0, self.columns + 1 - prompt.width))?;
+            write!(self.stdout, "{}\x1b[0m", prompt.text)?;
+            // The cursor may wait past the last column for the next character to wrap.
+            self.cursor.1 = 0;
+        }
+        self.right_prompt_drawn = right_prompt.is_some();
+
+        self.move_to(positions[self.position])?;
+        self.stdout.flush()
+    }
+
+    /// Moves the terminal cursor to a row of the input and a column.
+    fn move_to(&mut self, (row, column): (usize, usize)) -> io::Result<()> {
+        match row.cmp(&self.cursor.0) {
+            Ordering::Less => write!(self.stdout, "\x1b[{}A", self.cursor.0 - row)?,
+            Ordering::Greater => write!(self.stdout, "\x1b[{}B", row - self.cursor.0)?,
+            Ordering::Equal => {}
+        }
+        if column != self.cursor.1 {
+            write!(self.stdout, "\x1b[{}G", column)?;
+        }
+        self.cursor = (row, column);
         Ok(())
     }
+
+    /// Returns the row of the cursor, counted from the first row of the input, once the
+    /// terminal rewraps its lines to `columns`.
+    fn row_at(&self, columns: usize) -> usize {
+        self.layout(&self.drawn, columns)
+            .get(self.position)
+            .map_or(0, |&(row, _)| row)
+    }
+
+    /// Returns how many rows of the input are below the cursor.
+    fn below(&self) -> usize {
+        self.last_row - self.cursor.0
+    }
+
+    /// Returns the screen row of the cursor.
+    fn row(&self, events: &mut TerminalEvents) -> io::Result<usize> {
+        Ok(get_position(events, &mut &*self.stdout)?.0)
+    }
+
+    /// Prints `prompt` again at the cursor and returns a new input after it, with the same value
+    /// and cursor position, to be rendered.
+    fn reprint(&self, events: &mut TerminalEvents, prompt: &str) -> io::Result<Self> {
+        let mut stdout = self.stdout;
+        write!(stdout, "{}", prompt)?;
+        stdout.flush()?;
+        let mut input = Self::new(events, self.stdout)?;
+        input.right_prompt = self.right_prompt.clone();
+        input.continuation = self.continuation.clone();
+        input.columns = self.columns;
+        input.rows = self.rows;
+        input.push(&self.value);
+        input.seek(self.position);
+        input.mark = self.mark;
+        Ok(input)
+    }
+}
+
+/// Renders the input with its highlighting and, when the cursor is at its end, the rest of the
+/// most recent history entry starting with it, unless `AUTOSUGGEST` is `0` or `off`.
+fn render(shell: &Shell, input: &mut Input) -> io::Result<()> {
+    let enabled = !matches!(shell.variables.get("AUTOSUGGEST"), Some("0" | "off"));
+    input.suggestion.clear();
+    if enabled
+        && input.position == input.widths.len()
+        && !input.value.trim().is_empty()
+        && let Some(suggestion) = shell.history.suggestion(&input.value)
+    {
+        input.suggestion.push_str(suggestion);
+    }
+    input.render(&highlight(shell, &input.value))
+}
+
+/// Moves the cursor back to the start of `prompt` and erases the screen from there, given the
+/// width of the terminal and the row of the cursor counted from the last line of the prompt.
+fn rewind(stdout: &mut &io::Stdout, prompt: &Prompt, columns: usize, row: usize) -> io::Result<()> {
+    write!(stdout, "\r")?;
+    let rows = prompt.rows(columns) + row;
+    if rows > 0 {
+        write!(stdout, "\x1b[{}A", rows)?;
+    }
+    write!(stdout, "\x1b[J")
+}
+
+/// Exports the size of the terminal as `COLUMNS` and `LINES`.
+fn export_size(shell: &mut Shell, (columns, rows): (usize, usize)) {
+    for (name, value) in [("COLUMNS", columns), ("LINES", rows)] {
+        shell.variables.set(name, value.to_string());
+        shell.variables.export(name);
+    }
 }
 
 fn main() -> io::Result<()> {
-    let mut stdin: io::Stdin = io::stdin();
-    let mut stdout: io::Stdout = io::stdout();
+    width::set_locale();
 
-    // Set the terminal to the raw mode
-    unsafe {
-        let mut terminal_io_settings: libc::termios = std::mem::zeroed();
+    // The terminal is read without the buffering of `io::Stdin`, which polling it would not
+    // see.
+    let stdin = File::from(io::stdin().as_fd().try_clone_to_owned()?);
+    let mut stdout: &io::Stdout = &io::stdout();
 
-        libc::tcgetattr(stdout.as_raw_fd(), &mut terminal_io_settings) != -1
-            || return Err(io::Error::last_os_error());
+    let config = Config::new(Args::parse());
+    let mut shell = Shell::new(config);
+    if shell.variables.get("PS1").is_none() {
+        shell.variables.set("PS1", prompt::DEFAULT_PS1);
+    }
+    if shell.variables.get("PS2").is_none() {
+        shell.variables.set("PS2", prompt::DEFAULT_PS2);
+    }
+
+    let keymap_file = shell.config.home.join("keymap");
+    if keymap_file.is_file() {
+        match shell.keymap.load(&keymap_file) {
+            Ok(errors) => {
+                for (line, error) in errors {
+                    eprintln!("oxide-film: {}:{}: {}", keymap_file.display(), line, error);
+                }
+            }
+            Err(error) => eprintln!("oxide-film: {}: {}", keymap_file.display(), error),
+        }
+    }
+    let init_file = shell.config.init_file.clone();
+    if init_file.is_file()
+        && let Err(error) = shell.source(&init_file, &Streams::default())
+    {
+        eprintln!("oxide-film: {}: {}", init_file.display(), error);
+    }
+
+    // The history is set up by the variables the environment or the init file leave.
+    shell.history.configure(&shell.variables);
+    let history_file = shell
+        .variables
+        .get("HISTFILE")
+        .map_or_else(|| shell.config.history_file.clone(), PathBuf::from);
+    if let Err(error) = shell.history.open(history_file.clone()) {
+        eprintln!(
+            "oxide-film: {}: {}; the history will not be saved",
+            history_file.display(),
+            error
+        );
+    }
 
-        libc::cfmakeraw(&mut terminal_io_settings);
+    let terminal = Terminal::new(stdout.as_raw_fd())?;
+    terminal.raw()?;
+    let mut resized = terminal::resizes()?;
+    // The events live across commands, so that keys typed ahead of a prompt reach it.
+    let mut events = (&stdin).events();
+    let keyboard_protocol = query_keyboard_protocol(&mut events, &mut stdout)?;
+    export_size(&mut shell, terminal.size()?);
 
-        libc::tcsetattr(stdout.as_raw_fd(), libc::TCSANOW, &terminal_io_settings) != -1
-            || return Err(io::Error::last_os_error());
+    // Keyboard signals are meant for the foreground command, not for the shell.
+    unsafe {
+        libc::signal(libc::SIGINT, libc::SIG_IGN);
+        libc::signal(libc::SIGQUIT, libc::SIG_IGN);
+        libc::signal(libc::SIGTSTP, libc::SIG_IGN);
     }
 
+    // Text cut or copied from the input, the last one being yanked back. It goes to the
+    // clipboard of the terminal too.
+    let mut kill_ring: Vec<String> = Vec::new();
+
     'command: loop {
-        let prompt = format!(
-            //Prompt placeholder
-            "\x1b[0m\x1b[1m{} >\x1b[0m ",
-            std::env::current_dir()?
-                .display()
-                .to_string()
-                .replace(&std::env::var("HOME").unwrap(), "~")
-        );
-        write!(stdout, "{}", prompt)?;
+        shell.reap_jobs();
+
+        let ps1 = shell.parameter("PS1").unwrap_or_default();
+        let rprompt = shell.parameter("RPROMPT").unwrap_or_default();
+        let commands = prompt::segments(&ps1)
+            .into_iter()
+            .chain(prompt::segments(&rprompt))
+            .collect();
+        let mut segments = Segments::start(&mut shell, commands)?;
+
+        let mut prompt = Prompt::expand(&mut shell, &ps1, &segments.outputs)?;
+        // Bracketed paste keeps pasted newlines and tabs from running or completing the line,
+        // the terminal tells when its window gets and loses the focus,
+        // and mouse reports let clicks and drags place the cursor and select.
+        write!(
+            stdout,
+            "\x1b[?2004h\x1b[?1004h{}",
+            MouseTracking::Drags.enable(false)
+        )?;
+        if keyboard_protocol {
+            write!(stdout, "\x1b[={KEYBOARD_FLAGS};1u")?;
+        }
+        write!(stdout, "{}", prompt.text)?;
         stdout.flush()?;
-        let mut input = Input::new(&stdin, &mut stdout)?;
+        let mut input = Input::new(&mut events, stdout)?;
+        (input.columns, input.rows) = terminal.size()?;
+        let ps2 = shell.parameter("PS2").unwrap_or_default();
+        input.continuation = Prompt::expand(&mut shell, &ps2, &segments.outputs)?;
+        if !rprompt.is_empty() {
+            input.right_prompt = Some(Prompt::expand(&mut shell, &rprompt, &segments.outputs)?);
+        }
+        render(&shell, &mut input)?;
+        let mut navigation: Option<Navigation> = None;
+        let mut search: Option<Search> = None;
+        let mut menu: Option<Menu> = None;
+        let mut repaint = false;
+        // The time and place of the last click, which a second one makes a double click.
+        let mut clicked: Option<(Instant, (u16, u16))> = None;
+
+        // Like ncurses, in milliseconds.
+        events.escape_delay = shell
+            .parameter("ESCDELAY")
+            .and_then(|delay| delay.parse().ok())
+            .map_or(DEFAULT_ESCAPE_DELAY, Duration::from_millis);
+        loop {
+            // The prompt is redrawn as its segments finish, once nothing is drawn under it.
+            if repaint && menu.is_none() && search.is_none() {
+                repaint = false;
+                rewind(&mut stdout, &prompt, input.columns, input.cursor.0)?;
+                prompt = Prompt::expand(&mut shell, &ps1, &segments.outputs)?;
+                if let Some(right_prompt) = &mut input.right_prompt {
+                    *right_prompt = Prompt::expand(&mut shell, &rprompt, &segments.outputs)?;
+                }
+                input = input.reprint(&mut events, &prompt.text)?;
+                render(&shell, &mut input)?;
+            }
+
+            let mut fds = vec![resized.as_raw_fd()];
+            fds.extend(segments.fds());
+            let timeout = segments.pending().then(|| segments.timeout());
+            match events.wait(&fds, timeout)? {
+                Ready::Input => {}
+                Ready::Fd(0) => {
+                    while resized.read(&mut [0; 64]).is_ok_and(|read| read > 0) {}
+                    let size = terminal.size()?;
+                    export_size(&mut shell, size);
+                    if let Some(active) = &mut search {
+                        // Terminals rewrap the search line to the new width too.
+                        active.render(&shell.history, &mut stdout, size.0)?;
+                        (input.columns, input.rows) = size;
+                        continue;
+                    }
+
+                    // Terminals rewrap their lines to the new width, which the rows taken by the
+                    // prompt and the input are counted at.
+                    rewind(&mut stdout, &prompt, size.0, input.row_at(size.0))?;
+                    (input.columns, input.rows) = size;
+                    input = input.reprint(&mut events, &prompt.text)?;
+                    render(&shell, &mut input)?;
+                    if let Some(active) = &mut menu {
+                        let cursor = (input.row(&mut events)?, input.cursor.1);
+                        let rows = (input.below(), input.last_row + 1);
+                        active.render(&mut stdout, cursor, rows, size)?;
+                        input.top = active.top.saturating_sub(1 + input.last_row);
+                    }
+                    continue;
+                }
+                Ready::Fd(_) | Ready::Timeout => {
+                    repaint |= segments.collect()?;
+                    continue;
+                }
+            }
+            let Some(event) = events.next() else {
+                break;
+            };
+            // Keys act when they are pressed and repeated, not when they are released, and the
+            // focus and late replies of the terminal leave the input alone.
+            if let Ok(
+                Event::Key(_, _, KeyKind::Release)
+                | Event::FocusGained
+                | Event::FocusLost
+                | Event::Response(_),
+            ) = event
+            {
+                continue;
+            }
+
+            let action = match &event {
+                Ok(Event::Key(key, modifiers, _)) => shell.keymap.get(key, *modifiers),
+                // The wheel scrolls the menu, and is left to the history otherwise.
+                Ok(Event::Mouse(Mouse::Press(MouseButton::WheelUp, _, _), _)) if menu.is_some() => {
+                    Some(Action::MenuScrollUp)
+                }
+                Ok(Event::Mouse(Mouse::Press(MouseButton::WheelDown, _, _), _))
+                    if menu.is_some() =>
+                {
+                    Some(Action::MenuScrollDown)
+                }
+                _ => None,
+            };
+
+            if let Some(active) = &mut menu {
+                let handled = match (&event, action) {
+                    (_, Some(Action::Complete)) => {
+                        active.next();
+                        true
+                    }
+                    (_, Some(Action::CompleteBackward)) => {
+                        active.previous();
+                        true
+                    }
+                    (
+                        _,
+                        Some(
+                            action @ (Action::UpLineOrHistory
+                            | Action::DownLineOrHistory
+                            | Action::BackwardChar
+                            | Action::ForwardChar),
+                        ),
+                    ) if active.selected.is_some() => {
+                        match action {
+                            Action::UpLineOrHistory => active.vertical(-1),
+                            Action::DownLineOrHistory => active.vertical(1),
+                            Action::BackwardChar => active.horizontal(false),
+                            _ => active.horizontal(true),
+                        }
+                        true
+                    }
+                    (_, Some(Action::MenuPageUp)) => {
+                        active.page(false);
+                        true
+                    }
+                    (_, Some(Action::MenuPageDown)) => {
+                        active.page(true);
+                        true
+                    }
+                    (_, Some(Action::MenuScrollUp)) => {
+                        active.scroll(-1);
+                        true
+                    }
+                    (_, Some(Action::MenuScrollDown)) => {
+                        active.scroll(1);
+                        true
+                    }
+                    // A click selects the candidate under it.
+                    (Ok(Event::Mouse(Mouse::Press(MouseButton::Left, x, y), _)), _) => {
+                        if let Some(index) = active.at(*x as usize, *y as usize) {
+                            active.selected = Some(index);
+                        }
+                        true
+                    }
+                    (Ok(Event::Mouse(..)), _) => true,
+                    _ => false,
+                };
+
+                if handled {
+                    if let Some(candidate) = active.candidate() {
+                        let text = format!("{}{}", candidate.replacement, candidate.suffix);
+                        input.splice(active.inserted.clone(), &text);
+                        active.inserted.end = active.inserted.start + text.len();
+                    }
+                    render(&shell, &mut input)?;
+                    let cursor = (input.row(&mut events)?, input.cursor.1);
+                    let rows = (input.below(), input.last_row + 1);
+                    active.render(&mut stdout, cursor, rows, terminal.size()?)?;
+                    input.top = active.top.saturating_sub(1 + input.last_row);
+                    continue;
+                }
+
+                active.close(&mut stdout, input.cursor.1, input.below())?;
+                let selected = active.selected.is_some();
+                menu = None;
+                match action {
+                    // Enter takes the selected candidate without running the line yet.
+                    Some(Action::AcceptLine) if selected => continue,
+                    Some(Action::Abort) => continue,
+                    _ => {}
+                }
+            }
+
+            if let Some(active) = &mut search {
+                let accepted = match (&event, action) {
+                    (_, Some(Action::SearchBackward)) => {
+                        active.reverse();
+                        None
+                    }
+                    (_, Some(Action::SearchForward)) => {
+                        active.forward();
+                        None
+                    }
+                    (_, Some(Action::Abort)) => Some(None),
+                    (_, Some(Action::BackwardDeleteChar)) => {
+                        active.pop(&shell.history);
+                        None
+                    }
+                    (
+                        Ok(Event::Key(Key::Character(c), Modifiers::NONE | Modifiers::SHIFT, _)),
+                        None,
+                    ) if *c != '\n' => {
+                        active.push(&shell.history, c.encode_utf8(&mut [0; 4]));
+                        None
+                    }
+                    (Ok(Event::Paste(text) | Event::Clipboard(text)), _) => {
+                        active.push(&shell.history, text);
+                        None
+                    }
+                    (Ok(Event::Mouse(..)), _) => None,
+                    _ => Some(active.candidate(&shell.history).map(str::to_owned)),
+                };
+
+                match accepted {
+                    None => active.render(&shell.history, &mut stdout, input.columns)?,
+                    Some(accepted) => {
+                        active.erase(&mut stdout, input.columns)?;
+                        let value = accepted.unwrap_or_else(|| input.value.clone());
+                        search = None;
+
+                        input.replace(&value);
+                        input = input.reprint(&mut events, &prompt.text)?;
+                        render(&shell, &mut input)?;
+                    }
+                }
+                continue;
+            }
 
-        for event in stdin.events() {
             match &event {
                 Err(e) => {
                     write!(stdout, "\r\nError: {:?}\r\n", e)?;
@@ -115,59 +861,228 @@ fn main() -> io::Result<()> {
                     continue 'command;
                 }
                 Ok(event) => match event {
-                    Event::Key(key) => match key {
-                        Key::Ctrl(c) => match c.as_ref() {
-                            "d" => break 'command,
-                            "c" => {
-                                write!(stdout, "^C\r\n")?;
-                                stdout.flush()?;
-                                continue 'command;
+                    Event::Key(key, modifiers, _) => match action {
+                        Some(Action::AcceptLine) => {
+                            // An incomplete command continues on a new line.
+                            if !matches!(
+                                parser::parse(&input.value, &shell.aliases),
+                                Err(parser::Error::Incomplete)
+                            ) {
+                                break;
                             }
-                            _ => {
-                                write!(stdout, "\r\nUnhandled Ctrl key: {:?}\r\n", event)?;
+                            input.seek(input.widths.len());
+                            input.push("\n");
+                            navigation = None;
+                        }
+                        Some(Action::InsertNewline) => {
+                            input.push("\n");
+                            navigation = None;
+                        }
+                        Some(Action::Exit) => break 'command,
+                        Some(Action::SendBreak) => {
+                            input.suggestion.clear();
+                            input.seek(input.widths.len());
+                            input.render(&highlight(&shell, &input.value))?;
+                            write!(stdout, "^C\r\n")?;
+                            stdout.flush()?;
+                            continue 'command;
+                        }
+
+                        Some(Action::Abort) => {
+                            if input.mark.take().is_none() {
+                                write!(stdout, "\x07")?;
                                 stdout.flush()?;
-                                continue 'command;
                             }
-                        },
+                        }
 
-                        Key::Character(c) => {
-                            if c.as_ref() == "\n" {
-                                break;
+                        Some(Action::Complete | Action::CompleteBackward) => {
+                            let completion = match complete::complete(
+                                &mut shell,
+                                &input.value,
+                                input.byte_offset(),
+                            ) {
+                                Ok(completion) => completion,
+                                // The error is printed under the input, which is drawn again
+                                // after it.
+                                Err(error) => {
+                                    if input.below() > 0 {
+                                        write!(stdout, "\x1b[{}B", input.below())?;
+                                    }
+                                    write!(stdout, "\x07\r\n\x1b[Joxide-film: {}\r\n", error)?;
+                                    input = input.reprint(&mut events, &prompt.text)?;
+                                    render(&shell, &mut input)?;
+                                    navigation = None;
+                                    continue;
+                                }
+                            };
+                            let range = completion.range.clone();
+                            if let Some(insertion) = completion
+                                .insertion()
+                                .filter(|insertion| *insertion != input.value[range.clone()])
+                            {
+                                input.splice(range.clone(), &insertion);
                             }
 
-                            write!(stdout, "{}", c)?;
-                            stdout.flush()?;
+                            match completion.candidates.len() {
+                                0 => {
+                                    write!(stdout, "\x07")?;
+                                    stdout.flush()?;
+                                }
+                                1 => {}
+                                _ => {
+                                    render(&shell, &mut input)?;
+                                    let inserted = range.start..input.byte_offset();
+                                    let active = menu.insert(Menu::new(completion, inserted));
+                                    active.render(
+                                        &mut stdout,
+                                        (input.row(&mut events)?, input.cursor.1),
+                                        (input.below(), input.last_row + 1),
+                                        terminal.size()?,
+                                    )?;
+                                    // The screen scrolls up when the menu does not fit under
+                                    // the input.
+                                    input.top = active.top.saturating_sub(1 + input.last_row);
+                                }
+                            }
+                            navigation = None;
+                        }
 
-                            input.push(c);
-                            // input.push_str(c);
-                            // let new_column = get_column(&mut stdout, &stdin)?;
-                            // input_widths.push(new_column.saturating_sub(column));
-                            // position += 1;
-                            // column = new_column;
+                        Some(action @ (Action::SearchBackward | Action::SearchForward)) => {
+                            let direction = match action {
+                                Action::SearchBackward => search::Direction::Reverse,
+                                _ => search::Direction::Forward,
+                            };
+                            // The search line takes the place of the prompt and the input.
+                            rewind(&mut stdout, &prompt, input.columns, input.cursor.0)?;
+                            let active = search.insert(Search::new(
+                                search::Mode::from_variables(&shell.variables),
+                                direction,
+                            ));
+                            active.render(&shell.history, &mut stdout, input.columns)?;
+                            navigation = None;
                         }
 
-                        Key::Left => {
-                            if column > prompt_width {
-                                write!(stdout, "\x1b[{}D", input_widths[input_widths.len() - 1])?;
-                                stdout.flush()?;
+                        Some(Action::ForwardChar | Action::EndOfLine)
+                            if !input.suggestion.is_empty() =>
+                        {
+                            let suggestion = mem::take(&mut input.suggestion);
+                            input.push(&suggestion);
+                        }
+                        Some(Action::ForwardWord) if !input.suggestion.is_empty() => {
+                            input.accept_word();
+                        }
+                        Some(Action::BackwardChar) => input.left(),
+                        Some(Action::ForwardChar) => input.right(),
+                        Some(Action::ForwardWord) => input.word_right(),
+                        Some(Action::BeginningOfLine) => input.seek(0),
+                        Some(Action::EndOfLine) => input.seek(input.widths.len()),
+
+                        Some(Action::UpLineOrHistory) if input.vertical(false) => {}
+                        Some(Action::DownLineOrHistory) if input.vertical(true) => {}
+
+                        Some(Action::UpLineOrHistory) => {
+                            let navigation = navigation.get_or_insert_with(|| {
+                                Navigation::new(&input.value, &shell.history)
+                            });
+                            if let Some(entry) = navigation.previous(&shell.history) {
+                                input.replace(entry);
                             }
                         }
 
-                        Key::Backspace => {
-                            input.backspace()?;
-                            // if !input.is_empty() {
-                            //     input.pop();
-                            //     write!(stdout, "\x1b[{}D\x1b[K", input_widths.pop().unwrap())?;
-                            //     stdout.flush()?;
-                            // }
+                        Some(Action::DownLineOrHistory) => {
+                            if let Some(entry) =
+                                navigation.as_mut().and_then(|nav| nav.next(&shell.history))
+                            {
+                                input.replace(entry);
+                            }
                         }
 
-                        _ => {
-                            write!(stdout, "\r\nUnhandled key: {:?}\r\n", event)?;
+                        Some(Action::BackwardDeleteChar) => {
+                            input.backspace();
+                            navigation = None;
+                        }
+
+                        Some(Action::KillRegion) => {
+                            if let Some(text) = input.cut() {
+                                write!(stdout, "{}", Clipboard::copy(&text))?;
+                                kill_ring.push(text);
+                            }
+                        }
+                        Some(Action::CopyRegionAsKill) => {
+                            kill_ring.extend(input.selected());
+                            if let Some(text) = kill_ring.last() {
+                                write!(stdout, "{}", Clipboard::copy(text))?;
+                            }
+                        }
+                        Some(Action::Yank) => {
+                            if let Some(text) = kill_ring.last() {
+                                input.push(text);
+                            }
+                        }
+                        Some(Action::PasteClipboard) => {
+                            write!(stdout, "{}", Clipboard::REQUEST)?;
+                            stdout.flush()?;
+                        }
+                        // There is no menu to scroll.
+                        Some(
+                            Action::MenuPageUp
+                            | Action::MenuPageDown
+                            | Action::MenuScrollUp
+                            | Action::MenuScrollDown,
+                        ) => {
+                            write!(stdout, "\x07")?;
                             stdout.flush()?;
-                            continue 'command;
                         }
+
+                        None => match (key, *modifiers) {
+                            (Key::Character(c), Modifiers::NONE | Modifiers::SHIFT) => {
+                                input.push(c.encode_utf8(&mut [0; 4]));
+                                navigation = None;
+                            }
+                            _ => {
+                                write!(stdout, "\x07")?;
+                                stdout.flush()?;
+                            }
+                        },
                     },
+                    Event::Paste(text) | Event::Clipboard(text) => {
+                        input.push(text);
+                        navigation = None;
+                    }
+                    Event::Mouse(Mouse::Press(MouseButton::Left, x, y), _) => {
+                        input.mark = None;
+                        input.seek(input.at(*y as usize, *x as usize));
+                        let double = clicked.is_some_and(|(time, place)| {
+                            place == (*x, *y) && time.elapsed() < DOUBLE_CLICK
+                        });
+                        if double {
+                            input.select_word();
+                            clicked = None;
+                        } else {
+                            clicked = Some((Instant::now(), (*x, *y)));
+                        }
+                    }
+                    Event::Mouse(Mouse::Drag(MouseButton::Left, x, y), _) => {
+                        input.mark.get_or_insert(input.position);
+                        input.seek(input.at(*y as usize, *x as usize));
+                    }
+                    Event::Mouse(Mouse::Press(MouseButton::WheelUp, _, _), _) => {
+                        let navigation = navigation
+                            .get_or_insert_with(|| Navigation::new(&input.value, &shell.history));
+                        if let Some(entry) = navigation.previous(&shell.history) {
+                            input.replace(entry);
+                        }
+                    }
+                    Event::Mouse(Mouse::Press(MouseButton::WheelDown, _, _), _) => {
+                        if let Some(entry) =
+                            navigation.as_mut().and_then(|nav| nav.next(&shell.history))
+                        {
+                            input.replace(entry);
+                        }
+                    }
+                    Event::Mouse(..) => {}
+                    // Sequences the parser does not know are dropped rather than typed.
+                    Event::Unknown(_) => {}
                     _ => {
                         write!(stdout, "\r\nUnhandled event: {:?}\r\n", event)?;
                         stdout.flush()?;
@@ -175,11 +1090,55 @@ fn main() -> io::Result<()> {
                     }
                 },
             }
+
+            render(&shell, &mut input)?;
+        }
+
+        // The prompt is replaced by its compact version in the scrollback.
+        if let Some(transient) = shell
+            .parameter("TRANSIENT_PROMPT")
+            .filter(|transient| !transient.is_empty())
+        {
+            let transient = Prompt::expand(&mut shell, &transient, &segments.outputs)?;
+            rewind(&mut stdout, &prompt, input.columns, input.cursor.0)?;
+            input.right_prompt = None;
+            input = input.reprint(&mut events, &transient.text)?;
         }
 
-        write!(stdout, "\r\n{:?}\r\n", input)?;
+        // The suggestion is not left in the scrollback, and the output starts under the input.
+        input.suggestion.clear();
+        input.seek(input.widths.len());
+        input.render(&highlight(&shell, &input.value))?;
+        // Commands get the keys and the mouse the way they would without the shell.
+        write!(stdout, "\x1b[?2004l\x1b[?1004l{}", MouseTracking::disable())?;
+        if keyboard_protocol {
+            write!(stdout, "\x1b[=0;1u")?;
+        }
+        // A full last row has already moved the cursor to the next one.
+        if input.cursor.1 != 1 || input.drawn.last().is_none_or(|&(c, _)| c == '\n') {
+            write!(stdout, "\r\n")?;
+        }
         stdout.flush()?;
+
+        if !input.value.trim().is_empty() {
+            terminal.cooked()?;
+            if let Err(error) = shell.run_line(&input.value) {
+                eprintln!("oxide-film: {}", error);
+                shell.status = 1;
+            }
+            terminal.raw()?;
+        }
+
+        if shell.exit.is_some() {
+            break;
+        }
     }
 
-    Ok(())
+    write!(stdout, "\x1b[?2004l\x1b[?1004l{}", MouseTracking::disable())?;
+    if keyboard_protocol {
+        write!(stdout, "\x1b[=0;1u")?;
+    }
+    stdout.flush()?;
+    terminal.cooked()?;
+    process::exit(shell.exit.unwrap_or(shell.status))
 }
@@ -0,0 +1,373 @@
+use std::{
+    io::{self, Write},
+    ops::Range,
+};
+
+use crate::{
+    complete::{Candidate, Completion},
+    width,
+};
+
+/// The grid of completion candidates shown under the input line, filled column by column.
+#[derive(Debug)]
+pub struct Menu {
+    pub completion: Completion,
+    /// The byte range of the line holding the text inserted by the completion.
+    pub inserted: Range<usize>,
+    pub selected: Option<usize>,
+
+    columns: usize,
+    rows: usize,
+    column_width: usize,
+    /// How many rows of candidates fit on the screen under the input line.
+    visible: usize,
+    /// The first row of candidates shown.
+    scroll: usize,
+    /// The screen row of the first line of the menu, counted from 1 like mouse positions.
+    pub top: usize,
+}
+
+impl Menu {
+    pub fn new(completion: Completion, inserted: Range<usize>) -> Self {
+        Self {
+            completion,
+            inserted,
+            selected: None,
+
+            columns: 1,
+            rows: 0,
+            column_width: 0,
+            visible: 0,
+            scroll: 0,
+            top: 0,
+        }
+    }
+
+    pub fn candidate(&self) -> Option<&Candidate> {
+        self.completion.candidates.get(self.selected?)
+    }
+
+    fn len(&self) -> usize {
+        self.completion.candidates.len()
+    }
+
+    /// Fits the grid into a terminal of the given size, under an input taking `input_rows`
+    /// rows.
+    fn layout(&mut self, (width, height): (usize, usize), input_rows: usize) {
+        let widest = self
+            .completion
+            .candidates
+            .iter()
+            .map(|candidate| width::str_width(&candidate.display))
+            .max()
+            .unwrap_or(0);
+        self.column_width = (widest + 2).min(width.max(1));
+        self.columns = (width / self.column_width).max(1);
+        self.rows = self.len().div_ceil(self.columns);
+
+        // The input stays on the screen, and a status line is shown when rows are hidden.
+        let available = height.saturating_sub(input_rows).max(1);
+        self.visible = match self.rows <= available {
+            true => self.rows,
+            false => available.saturating_sub(1).max(1),
+        };
+        self.scroll = self.scroll.min(self.rows - self.visible);
+        if let Some(selected) = self.selected {
+            let row = selected % self.rows;
+            if row < self.scroll {
+                self.scroll = row;
+            } else if row >= self.scroll + self.visible {
+                self.scroll = row + 1 - self.visible;
+            }
+        }
+    }
+
+    /// Draws the menu under the input, given the screen row and column of the cursor, the
+    /// number of rows of the input below it and in all, and the size of the terminal, and puts
+    /// the cursor back.
+    pub fn render(
+        &mut self,
+        stdout: &mut impl Write,
+        cursor: (usize, usize),
+        (below, input_rows): (usize, usize),
+        size: (usize, usize),
+    ) -> io::Result<()> {
+        self.layout(size, input_rows);
+        self.erase(stdout, cursor.1, below)?;
+        if below > 0 {
+            write!(stdout, "\x1b[{}B", below)?;
+        }
+
+        let mut lines = 0;
+        for row in self.scroll..self.scroll + self.visible {
+            write!(stdout, "\r\n\x1b[K")?;
+            lines += 1;
+            for column in 0..self.columns {
+                let index = column * self.rows + row;
+                let Some(candidate) = self.completion.candidates.get(index) else {
+                    break;
+                };
+                let display = truncate(&candidate.display, self.column_width.saturating_sub(1));
+                let padding = self.column_width - width::str_width(display);
+                match self.selected == Some(index) {
+                    true => write!(stdout, "\x1b[7m{}\x1b[27m{:padding$}", display, "")?,
+                    false => write!(stdout, "{}{:padding$}", display, "")?,
+                }
+            }
+        }
+        if self.visible < self.rows {
+            write!(
+                stdout,
+                "\r\n\x1b[K\x1b[7mrows {}-{} of {}\x1b[27m",
+                self.scroll + 1,
+                self.scroll + self.visible,
+                self.rows
+            )?;
+            lines += 1;
+        }
+
+        // The screen scrolls up when the menu does not fit under the input line.
+        self.top = (cursor.0 + below).min(size.1.saturating_sub(lines)) + 1;
+        write!(stdout, "\x1b[{}A\x1b[{}G", lines + below, cursor.1)?;
+        stdout.flush()
+    }
+
+    /// Erases the menu, leaving the cursor at `column` of its row of the input, `below` rows
+    /// above the last one.
+    fn erase(&self, stdout: &mut impl Write, column: usize, below: usize) -> io::Result<()> {
+        if self.top > 0 {
+            let rows = below + 1;
+            write!(
+                stdout,
+                "\x1b[{}B\r\x1b[J\x1b[{}A\x1b[{}G",
+                rows, rows, column
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Erases the menu.
+    pub fn close(&self, stdout: &mut impl Write, column: usize, below: usize) -> io::Result<()> {
+        self.erase(stdout, column, below)?;
+        stdout.flush()
+    }
+
+    pub fn next(&mut self) {
+        self.selected = Some(
+            self.selected
+                .map_or(0, |selected| (selected + 1) % self.len()),
+        );
+    }
+
+    pub fn previous(&mut self) {
+        self.selected = Some(match self.selected {
+            None | Some(0) => self.len() - 1,
+            Some(selected) => selected - 1,
+        });
+    }
+
+    /// Moves the selection by `rows` rows within its column.
+    pub fn vertical(&mut self, rows: isize) {
+        let Some(selected) = self.selected else {
+            return self.next();
+        };
+        let column = selected / self.rows;
+        let last = (column * self.rows + self.rows - 1).min(self.len() - 1);
+        let row = (selected % self.rows).saturating_add_signed(rows);
+        self.selected = Some((column * self.rows + row).min(last));
+    }
+
+    /// Moves the selection to the next or previous column.
+    pub fn horizontal(&mut self, forward: bool) {
+        let Some(selected) = self.selected else {
+            return self.next();
+        };
+        self.selected = Some(match forward {
+            true if selected + self.rows < self.len() => selected + self.rows,
+            false if selected >= self.rows => selected - self.rows,
+            _ => selected,
+        });
+    }
+
+    /// Scrolls by `rows` rows, moving the selection along if there is one.
+    pub fn scroll(&mut self, rows: isize) {
+        match self.selected {
+            Some(_) => self.vertical(rows),
+            None => self.scroll = self.scroll.saturating_add_signed(rows),
+        }
+    }
+
+    /// Scrolls a page down or up.
+    pub fn page(&mut self, down: bool) {
+        let page = self.visible.max(1) as isize;
+        self.scroll(if down { page } else { -page });
+    }
+
+    /// Returns the candidate drawn at the given screen position.
+    pub fn at(&self, x: usize, y: usize) -> Option<usize> {
+        let row = y.checked_sub(self.top).filter(|&row| row < self.visible)? + self.scroll;
+        let column = x.checked_sub(1)? / self.column_width.max(1);
+        let index = column * self.rows + row;
+        (column < self.columns && index < self.len()).then_some(index)
+    }
+}
+
+/// Returns the longest start of `text` that is at most `columns` wide.
+fn truncate(text: &str, columns: usize) -> &str {
+    let mut width = 0;
+    let end = text
+        .char_indices()
+        .find(|&(_, c)| {
+            width += width::char_width(c);
+            width > columns
+        })
+        .map_or(text.len(), |(offset, _)| offset);
+    &text[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::complete::Context;
+
+    fn menu(displays: &[&str]) -> Menu {
+        let candidates = displays
+            .iter()
+            .map(|display| Candidate {
+                display: display.to_string(),
+                replacement: display.to_string(),
+                suffix: String::from(" "),
+            })
+            .collect();
+        let completion = Completion {
+            context: Context::Command,
+            range: 0..0,
+            candidates,
+        };
+        Menu::new(completion, 0..0)
+    }
+
+    /// Ten candidates in three columns of four rows.
+    fn grid() -> Menu {
+        let mut menu = menu(&["c0", "c1", "c2", "c3", "c4", "c5", "c6", "c7", "c8", "c9"]);
+        menu.layout((12, 24), 1);
+        menu
+    }
+
+    fn rendered(menu: &mut Menu, cursor: (usize, usize), size: (usize, usize)) -> String {
+        let mut output = Vec::new();
+        menu.render(&mut output, cursor, (0, 1), size).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn columns_are_as_wide_as_the_widest_candidate() {
+        let grid = grid();
+        assert_eq!(
+            (grid.column_width, grid.columns, grid.rows, grid.visible),
+            (4, 3, 4, 4)
+        );
+
+        width::set_locale();
+        let mut wide = menu(&["\u{754c}\u{754c}", "ab"]);
+        wide.layout((80, 24), 1);
+        assert_eq!((wide.column_width, wide.columns, wide.rows), (6, 13, 1));
+    }
+
+    #[test]
+    fn candidates_wider_than_the_terminal_are_cut() {
+        let mut menu = menu(&["abcdefghij", "xy"]);
+        let output = rendered(&mut menu, (1, 1), (5, 24));
+        assert_eq!((menu.column_width, menu.columns, menu.rows), (5, 1, 2));
+        assert!(output.contains("abcd "));
+        assert!(!output.contains("abcde"));
+    }
+
+    #[test]
+    fn a_terminal_shorter_than_the_input_shows_one_row() {
+        let mut menu = grid();
+        menu.layout((12, 3), 5);
+        assert_eq!(menu.visible, 1);
+
+        let output = rendered(&mut menu, (3, 1), (12, 3));
+        assert!(output.contains("rows 1-1 of 4"));
+        // The menu and its status line take the last two rows.
+        assert_eq!(menu.top, 2);
+    }
+
+    #[test]
+    fn the_selection_moves_within_columns_and_across_them() {
+        let mut menu = grid();
+        menu.vertical(1);
+        assert_eq!(menu.selected, Some(0));
+        menu.vertical(2);
+        assert_eq!(menu.selected, Some(2));
+        menu.vertical(5);
+        assert_eq!(menu.selected, Some(3));
+        menu.vertical(-9);
+        assert_eq!(menu.selected, Some(0));
+
+        menu.horizontal(true);
+        assert_eq!(menu.selected, Some(4));
+        menu.horizontal(true);
+        assert_eq!(menu.selected, Some(8));
+        menu.horizontal(true);
+        assert_eq!(menu.selected, Some(8));
+        // The last column is shorter than the others.
+        menu.vertical(3);
+        assert_eq!(menu.selected, Some(9));
+        menu.horizontal(false);
+        assert_eq!(menu.selected, Some(5));
+        menu.selected = Some(7);
+        menu.horizontal(true);
+        assert_eq!(menu.selected, Some(7));
+        menu.selected = Some(2);
+        menu.horizontal(false);
+        assert_eq!(menu.selected, Some(2));
+    }
+
+    #[test]
+    fn pages_scroll_by_the_visible_rows() {
+        let mut menu = grid();
+        // One row for the input and one for the status line leave three for candidates.
+        menu.layout((4, 5), 1);
+        assert_eq!((menu.columns, menu.rows, menu.visible), (1, 10, 3));
+
+        menu.page(true);
+        menu.layout((4, 5), 1);
+        assert_eq!(menu.scroll, 3);
+        for _ in 0..3 {
+            menu.page(true);
+        }
+        menu.layout((4, 5), 1);
+        assert_eq!(menu.scroll, 7);
+        menu.page(false);
+        menu.layout((4, 5), 1);
+        assert_eq!(menu.scroll, 4);
+
+        // The selection moves along, and the rows follow it.
+        menu.selected = Some(0);
+        menu.layout((4, 5), 1);
+        assert_eq!(menu.scroll, 0);
+        menu.page(true);
+        menu.layout((4, 5), 1);
+        assert_eq!((menu.selected, menu.scroll), (Some(3), 1));
+    }
+
+    #[test]
+    fn clicks_find_the_candidate_under_them() {
+        let mut menu = grid();
+        rendered(&mut menu, (5, 1), (12, 24));
+        assert_eq!(menu.top, 6);
+
+        assert_eq!(menu.at(1, 6), Some(0));
+        assert_eq!(menu.at(4, 7), Some(1));
+        assert_eq!(menu.at(5, 6), Some(4));
+        assert_eq!(menu.at(9, 7), Some(9));
+        // Above the menu, under it, past its columns and in the gap of its last column.
+        assert_eq!(menu.at(1, 5), None);
+        assert_eq!(menu.at(1, 10), None);
+        assert_eq!(menu.at(13, 6), None);
+        assert_eq!(menu.at(9, 8), None);
+    }
+}
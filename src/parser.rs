@@ -0,0 +1,616 @@
+use std::{collections::HashMap, fmt};
+
+use crate::lexer::{self, Kind, Token};
+
+/// Commands separated by `;`, `&` or newlines; the flag is set for the ones run in the
+/// background.
+pub type List = Vec<(AndOr, bool)>;
+
+#[derive(Debug, Clone)]
+pub struct AndOr {
+    pub first: Pipeline,
+    /// Pipelines following `&&` (`true`) or `||` (`false`).
+    pub rest: Vec<(bool, Pipeline)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Pipeline {
+    pub negated: bool,
+    pub commands: Vec<Command>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Command {
+    pub kind: CommandKind,
+    pub redirections: Vec<Redirection>,
+}
+
+#[derive(Debug, Clone)]
+pub enum CommandKind {
+    Simple {
+        assignments: Vec<String>,
+        words: Vec<String>,
+    },
+    Group(List),
+    Subshell(List),
+    If {
+        branches: Vec<(List, List)>,
+        otherwise: Option<List>,
+    },
+    Loop {
+        until: bool,
+        condition: List,
+        body: List,
+    },
+    For {
+        name: String,
+        words: Option<Vec<String>>,
+        body: List,
+    },
+    Function {
+        name: String,
+        body: Box<Command>,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct Redirection {
+    pub fd: Option<i32>,
+    pub operator: String,
+    pub target: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// The input ended before the command was complete, so it may continue on the next line.
+    Incomplete,
+    Unexpected(Token),
+    Unsupported(Token),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Incomplete => write!(f, "syntax error: unexpected end of input"),
+            Self::Unexpected(token) => {
+                write!(
+                    f,
+                    "syntax error near unexpected token `{}'",
+                    token.text.escape_debug()
+                )
+            }
+            Self::Unsupported(token) => write!(f, "`{}' is not supported", token.text),
+        }
+    }
+}
+
+/// Reserved words that end a list, so that the enclosing compound command can match them.
+const CLOSERS: &[&str] = &["}", "then", "elif", "else", "fi", "do", "done", "esac"];
+
+/// Parses `source`, substituting `aliases` for the command names they define.
+pub fn parse(source: &str, aliases: &HashMap<String, String>) -> Result<List, Error> {
+    let tokens: Vec<Token> = lexer::tokenize(source)
+        .into_iter()
+        .filter(|token| token.kind != Kind::Comment)
+        .collect();
+    if tokens.iter().any(|token| !token.terminated) {
+        return Err(Error::Incomplete);
+    }
+
+    let mut parser = Parser {
+        tokens,
+        position: 0,
+        aliases,
+        substituted: Vec::new(),
+    };
+    let list = parser.list()?;
+    match parser.peek() {
+        Some(token) => Err(Error::Unexpected(token.clone())),
+        None => Ok(list),
+    }
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    position: usize,
+    aliases: &'a HashMap<String, String>,
+    /// Aliases substituted so far, with the end of the tokens they expanded to, so that an
+    /// alias is not substituted again within its own expansion.
+    substituted: Vec<(String, usize)>,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Result<Token, Error> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .cloned()
+            .ok_or(Error::Incomplete)?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn at_operator(&self, operator: &str) -> bool {
+        self.peek()
+            .is_some_and(|token| token.kind == Kind::Operator && token.text == operator)
+    }
+
+    fn at_word(&self, word: &str) -> bool {
+        self.peek()
+            .is_some_and(|token| token.kind == Kind::Word && token.text == word)
+    }
+
+    fn expect_word(&mut self, word: &str) -> Result<(), Error> {
+        let token = self.next()?;
+        if token.kind == Kind::Word && token.text == word {
+            Ok(())
+        } else {
+            Err(Error::Unexpected(token))
+        }
+    }
+
+    fn linebreak(&mut self) {
+        while self.at_operator("\n") {
+            self.position += 1;
+        }
+    }
+
+    /// Replaces an alias at the current position by the tokens of its value.
+    fn substitute_aliases(&mut self) {
+        while let Some(token) = self.peek() {
+            let Some(value) = self.aliases.get(&token.text) else {
+                break;
+            };
+            if token.kind != Kind::Word
+                || self
+                    .substituted
+                    .iter()
+                    .any(|(name, end)| *name == token.text && self.position < *end)
+            {
+                break;
+            }
+
+            let name = token.text.clone();
+            let expansion: Vec<Token> = lexer::tokenize(value)
+                .into_iter()
+                .filter(|token| token.kind != Kind::Comment)
+                .collect();
+            let length = expansion.len();
+            for (_, end) in &mut self.substituted {
+                if *end > self.position {
+                    *end = *end + length - 1;
+                }
+            }
+            self.tokens
+                .splice(self.position..self.position + 1, expansion);
+            self.substituted.push((name, self.position + length));
+        }
+    }
+
+    fn list(&mut self) -> Result<List, Error> {
+        let mut list = List::new();
+        loop {
+            self.linebreak();
+            match self.peek() {
+                None => break,
+                Some(token) if token.kind == Kind::Operator && token.text == ")" => break,
+                Some(token) if token.kind == Kind::Word && CLOSERS.contains(&&*token.text) => {
+                    break;
+                }
+                Some(_) => {}
+            }
+
+            let and_or = self.and_or()?;
+            if self.at_operator("&") {
+                self.position += 1;
+                list.push((and_or, true));
+            } else if self.at_operator(";") || self.at_operator("\n") {
+                self.position += 1;
+                list.push((and_or, false));
+            } else {
+                list.push((and_or, false));
+                break;
+            }
+        }
+        Ok(list)
+    }
+
+    fn and_or(&mut self) -> Result<AndOr, Error> {
+        let first = self.pipeline()?;
+        let mut rest = Vec::new();
+        loop {
+            let and = if self.at_operator("&&") {
+                true
+            } else if self.at_operator("||") {
+                false
+            } else {
+                break;
+            };
+            self.position += 1;
+            self.linebreak();
+            rest.push((and, self.pipeline()?));
+        }
+        Ok(AndOr { first, rest })
+    }
+
+    fn pipeline(&mut self) -> Result<Pipeline, Error> {
+        let negated = self.at_word("!");
+        if negated {
+            self.position += 1;
+        }
+
+        let mut commands = vec![self.command()?];
+        while self.at_operator("|") {
+            self.position += 1;
+            self.linebreak();
+            commands.push(self.command()?);
+        }
+        Ok(Pipeline { negated, commands })
+    }
+
+    fn command(&mut self) -> Result<Command, Error> {
+        self.substitute_aliases();
+        let token = self.peek().cloned().ok_or(Error::Incomplete)?;
+
+        let kind = match (token.kind, token.text.as_str()) {
+            (Kind::Operator, "(") => {
+                self.position += 1;
+                let list = self.list()?;
+                let close = self.next()?;
+                if close.kind != Kind::Operator || close.text != ")" {
+                    return Err(Error::Unexpected(close));
+                }
+                CommandKind::Subshell(list)
+            }
+            (Kind::Word, "{") => {
+                self.position += 1;
+                let list = self.list()?;
+                self.expect_word("}")?;
+                CommandKind::Group(list)
+            }
+            (Kind::Word, "if") => self.if_clause()?,
+            (Kind::Word, keyword @ ("while" | "until")) => {
+                let until = keyword == "until";
+                self.position += 1;
+                let condition = self.list()?;
+                self.expect_word("do")?;
+                let body = self.list()?;
+                self.expect_word("done")?;
+                CommandKind::Loop {
+                    until,
+                    condition,
+                    body,
+                }
+            }
+            (Kind::Word, "for") => self.for_clause()?,
+            (Kind::Word, "case") => return Err(Error::Unsupported(token)),
+            (Kind::Word, word) if CLOSERS.contains(&word) || word == "in" => {
+                return Err(Error::Unexpected(token));
+            }
+            (Kind::Word, name)
+                if lexer::is_name(name)
+                    && self
+                        .tokens
+                        .get(self.position + 1)
+                        .is_some_and(|token| token.kind == Kind::Operator && token.text == "(") =>
+            {
+                let name = name.to_owned();
+                self.position += 2;
+                let close = self.next()?;
+                if close.kind != Kind::Operator || close.text != ")" {
+                    return Err(Error::Unexpected(close));
+                }
+                self.linebreak();
+                let body = self.command()?;
+                if matches!(body.kind, CommandKind::Simple { .. }) {
+                    return Err(Error::Unexpected(token));
+                }
+                CommandKind::Function {
+                    name,
+                    body: Box::new(body),
+                }
+            }
+            _ => return self.simple_command(),
+        };
+
+        let mut redirections = Vec::new();
+        while self
+            .peek()
+            .is_some_and(|token| token.kind == Kind::Redirection)
+        {
+            redirections.push(self.redirection()?);
+        }
+
+        Ok(Command { kind, redirections })
+    }
+
+    fn if_clause(&mut self) -> Result<CommandKind, Error> {
+        let mut branches = Vec::new();
+        let mut otherwise = None;
+
+        self.expect_word("if")?;
+        loop {
+            let condition = self.list()?;
+            self.expect_word("then")?;
+            let body = self.list()?;
+            branches.push((condition, body));
+
+            let token = self.next()?;
+            match token.text.as_str() {
+                "elif" if token.kind == Kind::Word => continue,
+                "else" if token.kind == Kind::Word => {
+                    otherwise = Some(self.list()?);
+                    self.expect_word("fi")?;
+                    break;
+                }
+                "fi" if token.kind == Kind::Word => break,
+                _ => return Err(Error::Unexpected(token)),
+            }
+        }
+
+        Ok(CommandKind::If {
+            branches,
+            otherwise,
+        })
+    }
+
+    fn for_clause(&mut self) -> Result<CommandKind, Error> {
+        self.expect_word("for")?;
+        let name = self.next()?;
+        if name.kind != Kind::Word || !lexer::is_name(&name.text) {
+            return Err(Error::Unexpected(name));
+        }
+
+        self.linebreak();
+        let mut words = None;
+        if self.at_word("in") {
+            self.position += 1;
+            let mut list = Vec::new();
+            while let Some(token) = self.peek() {
+                if token.kind != Kind::Word {
+                    break;
+                }
+                list.push(token.text.clone());
+                self.position += 1;
+            }
+            words = Some(list);
+        }
+        if self.at_operator(";") || self.at_operator("\n") {
+            self.position += 1;
+        }
+        self.linebreak();
+
+        self.expect_word("do")?;
+        let body = self.list()?;
+        self.expect_word("done")?;
+
+        Ok(CommandKind::For {
+            name: name.text,
+            words,
+            body,
+        })
+    }
+
+    fn simple_command(&mut self) -> Result<Command, Error> {
+        let mut assignments = Vec::new();
+        let mut words = Vec::new();
+        let mut redirections = Vec::new();
+
+        while let Some(token) = self.peek() {
+            match token.kind {
+                Kind::Redirection => redirections.push(self.redirection()?),
+                Kind::Word => {
+                    if words.is_empty() && lexer::is_assignment(&token.text) {
+                        assignments.push(token.text.clone());
+                    } else {
+                        words.push(token.text.clone());
+                    }
+                    self.position += 1;
+                }
+                _ => break,
+            }
+        }
+
+        if assignments.is_empty() && words.is_empty() && redirections.is_empty() {
+            return Err(match self.peek() {
+                Some(token) => Error::Unexpected(token.clone()),
+                None => Error::Incomplete,
+            });
+        }
+
+        Ok(Command {
+            kind: CommandKind::Simple { assignments, words },
+            redirections,
+        })
+    }
+
+    fn redirection(&mut self) -> Result<Redirection, Error> {
+        let token = self.next()?;
+        let digits = token.text.bytes().take_while(u8::is_ascii_digit).count();
+        let operator = token.text[digits..].to_owned();
+        if operator.starts_with("<<") {
+            return Err(Error::Unsupported(token));
+        }
+
+        let target = self.next()?;
+        if target.kind != Kind::Word {
+            return Err(Error::Unexpected(target));
+        }
+
+        Ok(Redirection {
+            fd: token.text[..digits].parse().ok(),
+            operator,
+            target: target.text,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> Result<List, Error> {
+        super::parse(source, &HashMap::new())
+    }
+
+    fn words(command: &Command) -> Vec<&str> {
+        match &command.kind {
+            CommandKind::Simple { words, .. } => words.iter().map(String::as_str).collect(),
+            kind => panic!("not a simple command: {:?}", kind),
+        }
+    }
+
+    fn unexpected(source: &str) -> String {
+        match parse(source) {
+            Err(Error::Unexpected(token)) => token.text,
+            result => panic!("{:?} parsed to {:?}", source, result),
+        }
+    }
+
+    #[test]
+    fn lists_keep_their_separators() {
+        let list = parse("a && b || ! c | d; e &\nf").unwrap();
+        assert_eq!(
+            list.iter()
+                .map(|(_, background)| *background)
+                .collect::<Vec<_>>(),
+            [false, true, false]
+        );
+
+        let (and_or, _) = &list[0];
+        assert_eq!(words(&and_or.first.commands[0]), ["a"]);
+        assert_eq!(
+            and_or.rest.iter().map(|(and, _)| *and).collect::<Vec<_>>(),
+            [true, false]
+        );
+        let pipeline = &and_or.rest[1].1;
+        assert!(pipeline.negated);
+        assert_eq!(
+            pipeline.commands.iter().map(words).collect::<Vec<_>>(),
+            [["c"], ["d"]]
+        );
+    }
+
+    #[test]
+    fn simple_commands_split_assignments_and_redirections() {
+        let list = parse("a=1 b=2 cmd c=3 2>err >>out <in").unwrap();
+        let command = &list[0].0.first.commands[0];
+        let CommandKind::Simple { assignments, words } = &command.kind else {
+            panic!("{:?}", command);
+        };
+        assert_eq!(assignments, &["a=1", "b=2"]);
+        assert_eq!(words, &["cmd", "c=3"]);
+        assert_eq!(
+            command
+                .redirections
+                .iter()
+                .map(|r| (r.fd, &*r.operator, &*r.target))
+                .collect::<Vec<_>>(),
+            [
+                (Some(2), ">", "err"),
+                (None, ">>", "out"),
+                (None, "<", "in")
+            ]
+        );
+    }
+
+    #[test]
+    fn compound_commands_nest() {
+        let list = parse("(a; b) >out; { c; }; f() { d; }").unwrap();
+        let commands: Vec<&Command> = list
+            .iter()
+            .map(|(and_or, _)| &and_or.first.commands[0])
+            .collect();
+        assert!(matches!(&commands[0].kind, CommandKind::Subshell(list) if list.len() == 2));
+        assert_eq!(commands[0].redirections[0].target, "out");
+        assert!(matches!(&commands[1].kind, CommandKind::Group(list) if list.len() == 1));
+        assert!(matches!(
+            &commands[2].kind,
+            CommandKind::Function { name, body } if name == "f"
+                && matches!(body.kind, CommandKind::Group(_))
+        ));
+
+        let list = parse("if a; then b; elif c; then d; else e; fi").unwrap();
+        let CommandKind::If {
+            branches,
+            otherwise,
+        } = &list[0].0.first.commands[0].kind
+        else {
+            panic!("{:?}", list);
+        };
+        assert_eq!(branches.len(), 2);
+        assert!(otherwise.is_some());
+
+        let list = parse("for i in a 'b c'\ndo until x; do y; done; done").unwrap();
+        let CommandKind::For { name, words, body } = &list[0].0.first.commands[0].kind else {
+            panic!("{:?}", list);
+        };
+        assert_eq!(name, "i");
+        assert_eq!(
+            words.as_deref(),
+            Some(&["a".to_owned(), "'b c'".to_owned()][..])
+        );
+        assert!(matches!(
+            body[0].0.first.commands[0].kind,
+            CommandKind::Loop { until: true, .. }
+        ));
+    }
+
+    #[test]
+    fn unfinished_commands_are_incomplete() {
+        for source in [
+            "a &&",
+            "a |",
+            "if a; then b",
+            "while a; do",
+            "(a",
+            "{ a;",
+            "f()",
+            "echo 'a",
+            "a >",
+            "for i in a;",
+        ] {
+            assert_eq!(
+                parse(source).unwrap_err(),
+                Error::Incomplete,
+                "{:?}",
+                source
+            );
+        }
+        assert!(parse("").unwrap().is_empty());
+        assert!(parse("# comment\n\n").unwrap().is_empty());
+    }
+
+    #[test]
+    fn misplaced_tokens_are_unexpected() {
+        assert_eq!(unexpected(")"), ")");
+        assert_eq!(unexpected("a; fi"), "fi");
+        assert_eq!(unexpected("if a; then b; done"), "done");
+        assert_eq!(unexpected("a | ;"), ";");
+        assert_eq!(unexpected("f() a"), "f");
+        assert_eq!(unexpected("for 1 in a; do b; done"), "1");
+        assert!(matches!(
+            parse("case a in esac"),
+            Err(Error::Unsupported(_))
+        ));
+        assert!(matches!(parse("cat <<end"), Err(Error::Unsupported(_))));
+    }
+
+    #[test]
+    fn aliases_expand_once_in_command_position() {
+        let aliases = HashMap::from([
+            ("ls".to_owned(), "ls -F".to_owned()),
+            ("l".to_owned(), "ls -l".to_owned()),
+            ("both".to_owned(), "a; b".to_owned()),
+        ]);
+        let list = super::parse("l l; ls | both", &aliases).unwrap();
+        assert_eq!(words(&list[0].0.first.commands[0]), ["ls", "-F", "-l", "l"]);
+        assert_eq!(words(&list[1].0.first.commands[0]), ["ls", "-F"]);
+        assert_eq!(words(&list[1].0.first.commands[1]), ["a"]);
+        assert_eq!(words(&list[2].0.first.commands[0]), ["b"]);
+    }
+}
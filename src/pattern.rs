@@ -0,0 +1,169 @@
+use std::{fs, path::Path};
+
+/// Whether `pattern` contains unquoted `*`, `?` or `[` characters.
+pub fn has_magic(pattern: &str) -> bool {
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            '*' | '?' | '[' => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Removes the backslashes quoting pattern characters.
+pub fn unescape(pattern: &str) -> String {
+    let mut result = String::with_capacity(pattern.len());
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => result.extend(chars.next()),
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+/// Matches `text` against a shell pattern, in which a backslash quotes the next character.
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    matches_chars(&pattern, &text)
+}
+
+fn matches_chars(pattern: &[char], text: &[char]) -> bool {
+    match pattern.first() {
+        None => text.is_empty(),
+        Some('*') => (0..=text.len()).any(|skip| matches_chars(&pattern[1..], &text[skip..])),
+        Some('?') => !text.is_empty() && matches_chars(&pattern[1..], &text[1..]),
+        Some('[') => match (bracket(pattern), text.first()) {
+            (Some((matched, length)), Some(&c)) => {
+                matched(c) && matches_chars(&pattern[length..], &text[1..])
+            }
+            (Some(_), None) => false,
+            // An unclosed bracket matches itself.
+            (None, _) => text.first() == Some(&'[') && matches_chars(&pattern[1..], &text[1..]),
+        },
+        Some('\\') if pattern.len() > 1 => {
+            text.first() == Some(&pattern[1]) && matches_chars(&pattern[2..], &text[1..])
+        }
+        Some(c) => text.first() == Some(c) && matches_chars(&pattern[1..], &text[1..]),
+    }
+}
+
+/// Parses the bracket expression at the start of `pattern`, returning its predicate and
+/// length.
+fn bracket(pattern: &[char]) -> Option<(impl Fn(char) -> bool, usize)> {
+    let mut i = 1;
+    let negated = matches!(pattern.get(i), Some('!' | '^'));
+    if negated {
+        i += 1;
+    }
+
+    let mut items: Vec<(char, char)> = Vec::new();
+    let mut classes: Vec<String> = Vec::new();
+    let start = i;
+    loop {
+        let c = *pattern.get(i)?;
+        if c == ']' && i > start {
+            i += 1;
+            break;
+        }
+
+        if c == '[' && pattern.get(i + 1) == Some(&':') {
+            let rest: String = pattern[i + 2..].iter().collect();
+            if let Some(end) = rest.find(":]") {
+                classes.push(rest[..end].to_owned());
+                i += 2 + rest[..end].chars().count() + 2;
+                continue;
+            }
+        }
+
+        let (low, next) = match c {
+            '\\' => (*pattern.get(i + 1)?, i + 2),
+            c => (c, i + 1),
+        };
+        if pattern.get(next) == Some(&'-') && pattern.get(next + 1).is_some_and(|&c| c != ']') {
+            items.push((low, pattern[next + 1]));
+            i = next + 2;
+        } else {
+            items.push((low, low));
+            i = next;
+        }
+    }
+
+    let matched = move |c: char| {
+        let matched = items.iter().any(|&(low, high)| low <= c && c <= high)
+            || classes.iter().any(|class| match class.as_str() {
+                "alnum" => c.is_alphanumeric(),
+                "alpha" => c.is_alphabetic(),
+                "blank" => c == ' ' || c == '\t',
+                "digit" => c.is_ascii_digit(),
+                "lower" => c.is_lowercase(),
+                "punct" => c.is_ascii_punctuation(),
+                "space" => c.is_whitespace(),
+                "upper" => c.is_uppercase(),
+                "xdigit" => c.is_ascii_hexdigit(),
+                _ => false,
+            });
+        matched != negated
+    };
+    Some((matched, i))
+}
+
+/// Expands a pathname pattern into the sorted list of existing paths matching it.
+pub fn glob(pattern: &str) -> Vec<String> {
+    let (mut paths, rest) = match pattern.strip_prefix('/') {
+        Some(rest) => (vec![String::from("/")], rest),
+        None => (vec![String::new()], pattern),
+    };
+
+    for component in rest.split('/') {
+        if component.is_empty() {
+            continue;
+        }
+
+        let mut next = Vec::new();
+        for path in &paths {
+            if !has_magic(component) {
+                let candidate = format!("{}{}", path, unescape(component));
+                if Path::new(&candidate).symlink_metadata().is_ok() {
+                    next.push(candidate);
+                }
+                continue;
+            }
+
+            let directory = if path.is_empty() { "." } else { path.as_str() };
+            let Ok(entries) = fs::read_dir(directory) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                // Hidden files are only matched by patterns starting with a dot.
+                if name.starts_with('.') && !component.starts_with('.') {
+                    continue;
+                }
+                if matches(component, &name) {
+                    next.push(format!("{}{}", path, name));
+                }
+            }
+        }
+
+        paths = next.into_iter().map(|path| path + "/").collect();
+    }
+
+    let mut paths: Vec<String> = paths
+        .into_iter()
+        .map(|path| match pattern.ends_with('/') {
+            true => path,
+            false => path.trim_end_matches('/').to_owned(),
+        })
+        .filter(|path| !path.is_empty())
+        .collect();
+    paths.sort();
+    paths
+}
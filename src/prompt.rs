@@ -0,0 +1,459 @@
+use std::{
+    collections::HashMap,
+    env,
+    ffi::CStr,
+    fs::File,
+    io::{self, PipeReader, PipeWriter, Read, Write},
+    iter::Peekable,
+    mem,
+    os::fd::{AsRawFd, RawFd},
+    str::Chars,
+    time::{Duration, Instant},
+};
+
+use crate::{expand, shell::Shell, width};
+
+/// Start and end of a non-printing sequence, marked by `\[` and `\]`.
+const START_IGNORE: char = '\x01';
+const END_IGNORE: char = '\x02';
+
+pub const DEFAULT_PS1: &str = "\\[\\e[0m\\e[1m\\]\\w >\\[\\e[0m\\] ";
+pub const DEFAULT_PS2: &str = "> ";
+
+/// Shown in place of a segment until its output arrives, unless `PROMPT_PLACEHOLDER` is set.
+const DEFAULT_PLACEHOLDER: &str = "…";
+/// How long segments may run before they are left empty, unless `PROMPT_TIMEOUT` is set.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Prompt {
+    pub text: String,
+    /// The number of columns the last line of the prompt takes, without its non-printing
+    /// sequences.
+    pub width: usize,
+    /// The widths of the lines before the last one.
+    pub lines: Vec<usize>,
+}
+
+impl Prompt {
+    /// Expands a prompt string: its backslash escapes, then `!` as the history number and the
+    /// parameter expansions and command substitutions.
+    ///
+    /// A segment `\{command\}` is replaced by the output of `command` found in `segments`, or by
+    /// a placeholder while it is being computed.
+    pub fn expand(
+        shell: &mut Shell,
+        prompt: &str,
+        segments: &HashMap<String, String>,
+    ) -> io::Result<Self> {
+        let placeholder = shell
+            .parameter("PROMPT_PLACEHOLDER")
+            .unwrap_or_else(|| String::from(DEFAULT_PLACEHOLDER));
+
+        let mut word = String::with_capacity(prompt.len());
+        let mut chars = prompt.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some('[') => word.push(START_IGNORE),
+                    Some(']') => word.push(END_IGNORE),
+                    Some('{') => {
+                        let output = segments.get(&segment(&mut chars));
+                        word.push_str(&quote(output.unwrap_or(&placeholder)));
+                    }
+                    Some(escape) => match escape_value(shell, escape) {
+                        Some(value) => word.push_str(&quote(&value)),
+                        None => {
+                            word.push('\\');
+                            word.push(escape);
+                        }
+                    },
+                    None => word.push('\\'),
+                },
+                '!' if chars.peek() == Some(&'!') => {
+                    chars.next();
+                    word.push_str("\\!");
+                }
+                '!' => word.push_str(&shell.history.next_number().to_string()),
+                c => word.push(c),
+            }
+        }
+
+        // The terminal is in raw mode, so an expansion error is shown as the prompt rather than
+        // printed.
+        let expanded = match expand::string(shell, &word) {
+            Ok(expanded) => expanded,
+            Err(error) if expand::is_error(&error) => format!("oxide-film: {}\n", error),
+            Err(error) => return Err(error),
+        };
+        let mut lines: Vec<usize> = expanded.split('\n').map(printed_width).collect();
+        Ok(Self {
+            width: lines.pop().unwrap_or(0),
+            lines,
+            // The terminal is in raw mode, in which a newline does not return the carriage.
+            text: expanded
+                .replace([START_IGNORE, END_IGNORE], "")
+                .replace('\n', "\r\n"),
+        })
+    }
+
+    /// Returns how many rows the lines of the prompt before the last one take on a terminal
+    /// `columns` wide.
+    pub fn rows(&self, columns: usize) -> usize {
+        self.lines
+            .iter()
+            .map(|width| width.div_ceil(columns.max(1)).max(1))
+            .sum()
+    }
+}
+
+/// Returns the command of the segment whose `\{` was just read, consuming its `\}`.
+fn segment(chars: &mut Peekable<Chars>) -> String {
+    let mut command = String::new();
+    while let Some(c) = chars.next() {
+        if c == '\\' && chars.peek() == Some(&'}') {
+            chars.next();
+            break;
+        }
+        command.push(c);
+    }
+    command
+}
+
+/// Returns the commands of the segments of a prompt string.
+pub fn segments(prompt: &str) -> Vec<String> {
+    let mut commands = Vec::new();
+    let mut chars = prompt.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\\' && chars.next() == Some('{') {
+            commands.push(segment(&mut chars));
+        }
+    }
+    commands
+}
+
+/// The segments of the prompts being computed in the background.
+///
+/// Each command runs in a child process forked from the shell, so it sees its functions and
+/// variables, and its output comes through a pipe the line editor waits on with the keyboard.
+/// The child leads a process group of its own, which is killed as a whole when it times out,
+/// so that the commands it started do not keep the pipe open.
+#[derive(Debug)]
+pub struct Segments {
+    pub outputs: HashMap<String, String>,
+    /// The commands still running, with their process, their pipe and their output so far.
+    running: HashMap<String, (libc::pid_t, PipeReader, Vec<u8>)>,
+    deadline: Instant,
+}
+
+impl Segments {
+    pub fn start(shell: &mut Shell, commands: Vec<String>) -> io::Result<Self> {
+        let timeout = shell
+            .parameter("PROMPT_TIMEOUT")
+            .and_then(|timeout| timeout.parse().ok())
+            .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+            .unwrap_or(DEFAULT_TIMEOUT);
+
+        let mut running = HashMap::new();
+        for command in commands {
+            if running.contains_key(&command) {
+                continue;
+            }
+            let (reader, writer) = io::pipe()?;
+            if unsafe { libc::fcntl(reader.as_raw_fd(), libc::F_SETFL, libc::O_NONBLOCK) } == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            let pid = unsafe { libc::fork() };
+            if pid == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            if pid == 0 {
+                drop(reader);
+                run_segment(shell, &command, writer);
+            }
+            // Both processes set the group, so that it is set before either one goes on.
+            unsafe { libc::setpgid(pid, pid) };
+            drop(writer);
+            running.insert(command, (pid, reader, Vec::new()));
+        }
+
+        Ok(Self {
+            outputs: HashMap::new(),
+            running,
+            deadline: Instant::now() + timeout,
+        })
+    }
+
+    pub fn pending(&self) -> bool {
+        !self.running.is_empty()
+    }
+
+    /// The pipes of the segments still running, which become readable as they write and end.
+    pub fn fds(&self) -> impl Iterator<Item = RawFd> + '_ {
+        self.running
+            .values()
+            .map(|(_, reader, _)| reader.as_raw_fd())
+    }
+
+    /// The time left until the segments time out.
+    pub fn timeout(&self) -> Duration {
+        self.deadline.saturating_duration_since(Instant::now())
+    }
+
+    /// Reads the output of the segments, keeping the ones that finished, and gives up on the
+    /// others once they time out. Returns whether any output changed.
+    pub fn collect(&mut self) -> io::Result<bool> {
+        let mut finished = Vec::new();
+        for (command, (_, reader, output)) in &mut self.running {
+            match reader.read_to_end(output) {
+                Ok(_) => finished.push(command.clone()),
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => {}
+                Err(error) => return Err(error),
+            }
+        }
+
+        let mut changed = false;
+        for command in finished {
+            if let Some((pid, _, output)) = self.running.remove(&command) {
+                unsafe { libc::waitpid(pid, std::ptr::null_mut(), 0) };
+                let output = String::from_utf8_lossy(&output).into_owned();
+                self.outputs.insert(command, output);
+                changed = true;
+            }
+        }
+
+        if self.pending() && Instant::now() >= self.deadline {
+            for (command, (pid, _, _)) in self.running.drain() {
+                kill(pid);
+                self.outputs.insert(command, String::new());
+            }
+            changed = true;
+        }
+        Ok(changed)
+    }
+}
+
+impl Drop for Segments {
+    fn drop(&mut self) {
+        for (pid, _, _) in self.running.values() {
+            kill(*pid);
+        }
+    }
+}
+
+/// Kills the process group of a segment and waits for its child.
+fn kill(pid: libc::pid_t) {
+    unsafe {
+        libc::kill(-pid, libc::SIGKILL);
+        libc::waitpid(pid, std::ptr::null_mut(), 0);
+    }
+}
+
+/// Runs the command of a segment in the forked child, writing its output to `writer`.
+fn run_segment(shell: &mut Shell, command: &str, mut writer: PipeWriter) -> ! {
+    unsafe { libc::setpgid(0, 0) };
+    // Segments must neither read the keyboard nor write over the line being edited.
+    if let Ok(null) = File::options().read(true).write(true).open("/dev/null") {
+        unsafe {
+            libc::dup2(null.as_raw_fd(), libc::STDIN_FILENO);
+            libc::dup2(null.as_raw_fd(), libc::STDERR_FILENO);
+        }
+    }
+    if let Ok(output) = shell.capture(command) {
+        let _ = writer.write_all(output.as_bytes());
+    }
+    unsafe { libc::_exit(0) }
+}
+
+/// Returns the value of the prompt escape `\c`, or `None` if it is not one.
+fn escape_value(shell: &Shell, c: char) -> Option<String> {
+    Some(match c {
+        'a' => String::from("\x07"),
+        'e' => String::from("\x1b"),
+        'n' => String::from("\n"),
+        '\\' => String::from("\\"),
+        'u' => shell.parameter("USER").unwrap_or_else(user),
+        'h' => hostname().split('.').next().unwrap_or_default().to_owned(),
+        'H' => hostname(),
+        'w' | 'W' => {
+            let directory = shell
+                .parameter("PWD")
+                .or_else(|| Some(env::current_dir().ok()?.to_string_lossy().into_owned()))
+                .unwrap_or_default();
+            let home = shell.parameter("HOME").unwrap_or_default();
+            match directory.strip_prefix(home.as_str()) {
+                _ if home.is_empty() => directory,
+                Some("") => String::from("~"),
+                Some(rest) if c == 'w' && rest.starts_with('/') => format!("~{}", rest),
+                _ if c == 'w' || directory == "/" => directory,
+                _ => directory.rsplit('/').next().unwrap_or_default().to_owned(),
+            }
+        }
+        '$' => String::from(if unsafe { libc::geteuid() } == 0 {
+            "#"
+        } else {
+            "$"
+        }),
+        't' => time(),
+        'j' => shell.jobs.len().to_string(),
+        '?' => shell.status.to_string(),
+        _ => return None,
+    })
+}
+
+/// Quotes the value of an escape against the expansions applied to the prompt afterwards.
+fn quote(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len());
+    for c in value.chars() {
+        if "\\$`\"'~".contains(c) {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted
+}
+
+/// Returns the width of `text` without the sequences marked as non-printing and the escape
+/// sequences that were not marked.
+pub fn printed_width(text: &str) -> usize {
+    let mut width = 0;
+    let mut ignored = false;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            START_IGNORE => ignored = true,
+            END_IGNORE => ignored = false,
+            _ if ignored => {}
+            '\x1b' => {
+                // A CSI sequence ends with a byte in the `@`-`~` range.
+                if chars.next() == Some('[') {
+                    for c in chars.by_ref() {
+                        if ('@'..='~').contains(&c) {
+                            break;
+                        }
+                    }
+                }
+            }
+            '\n' => width = 0,
+            c => width += width::char_width(c),
+        }
+    }
+    width
+}
+
+fn user() -> String {
+    unsafe {
+        let passwd = libc::getpwuid(libc::geteuid());
+        match passwd.is_null() {
+            true => String::new(),
+            false => CStr::from_ptr((*passwd).pw_name)
+                .to_string_lossy()
+                .into_owned(),
+        }
+    }
+}
+
+fn hostname() -> String {
+    let mut buffer = [0u8; 256];
+    unsafe {
+        if libc::gethostname(buffer.as_mut_ptr().cast(), buffer.len()) == -1 {
+            return String::new();
+        }
+    }
+    CStr::from_bytes_until_nul(&buffer)
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// The current time as `HH:MM:SS`.
+fn time() -> String {
+    unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        let mut tm: libc::tm = mem::zeroed();
+        libc::localtime_r(&now, &mut tm);
+        format!("{:02}:{:02}:{:02}", tm.tm_hour, tm.tm_min, tm.tm_sec)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(shell: &mut Shell, prompt: &str) -> Prompt {
+        Prompt::expand(shell, prompt, &HashMap::new()).unwrap()
+    }
+
+    #[test]
+    fn escapes_expand_to_the_shell_state() {
+        let mut shell = Shell::for_tests();
+        shell.variables.set("USER", "ferris");
+        shell.variables.set("HOME", "/home/ferris");
+        shell.variables.set("PWD", "/home/ferris/src/crate");
+        shell.status = 2;
+        shell.jobs = vec![1, 2, 3];
+
+        let host = hostname();
+        let prompt = expand(&mut shell, "\\u@\\h:\\w \\W \\j \\?\\$ ");
+        let sign = if unsafe { libc::geteuid() } == 0 {
+            '#'
+        } else {
+            '$'
+        };
+        let text = format!(
+            "ferris@{}:~/src/crate crate 3 2{sign} ",
+            host.split('.').next().unwrap()
+        );
+        assert_eq!(prompt.text, text);
+        assert_eq!(prompt.width, text.len());
+        assert!(prompt.lines.is_empty());
+
+        let time = expand(&mut shell, "\\t").text;
+        assert_eq!(time.len(), 8);
+        assert!(time.chars().enumerate().all(|(i, c)| match i % 3 {
+            2 => c == ':',
+            _ => c.is_ascii_digit(),
+        }));
+    }
+
+    #[test]
+    fn directories_are_shortened_only_under_home() {
+        let mut shell = Shell::for_tests();
+        shell.variables.set("HOME", "/home/ferris");
+        for (pwd, w, big_w) in [
+            ("/home/ferris", "~", "~"),
+            ("/home/ferrisx/a", "/home/ferrisx/a", "a"),
+            ("/usr/lib", "/usr/lib", "lib"),
+            ("/", "/", "/"),
+        ] {
+            shell.variables.set("PWD", pwd);
+            assert_eq!(expand(&mut shell, "\\w").text, w, "\\w in {pwd}");
+            assert_eq!(expand(&mut shell, "\\W").text, big_w, "\\W in {pwd}");
+        }
+    }
+
+    #[test]
+    fn escape_values_are_not_expanded_again() {
+        let mut shell = Shell::for_tests();
+        shell.variables.set("USER", "~$(echo no)`x`\\");
+        shell.variables.set("X", "yes");
+        assert_eq!(expand(&mut shell, "\\u $X").text, "~$(echo no)`x`\\ yes");
+    }
+
+    #[test]
+    fn non_printing_sequences_take_no_columns() {
+        width::set_locale();
+        let mut shell = Shell::for_tests();
+        let prompt = expand(
+            &mut shell,
+            "\\[\\e[1m\\]ab\\[\\e[0m\\]\\n\\[\\e]0;title\\a\\]界 ",
+        );
+        assert_eq!(prompt.text, "\x1b[1mab\x1b[0m\r\n\x1b]0;title\x07界 ");
+        assert_eq!(prompt.lines, [2]);
+        assert_eq!(prompt.width, 3);
+
+        // Unmarked CSI sequences are skipped all the same.
+        assert_eq!(expand(&mut shell, "\\e[31m> ").width, 2);
+        shell.variables.set("PWD", "/tmp");
+        assert_eq!(expand(&mut shell, DEFAULT_PS1).width, "/tmp > ".len());
+    }
+}
@@ -0,0 +1,347 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::{self, Write},
+};
+
+use crate::{history::History, variables::Variables, width};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    Substring,
+    Fuzzy,
+}
+
+impl Mode {
+    /// Returns the mode `HISTSEARCH` asks for.
+    pub fn from_variables(variables: &Variables) -> Self {
+        match variables.get("HISTSEARCH") {
+            Some("fuzzy") => Self::Fuzzy,
+            _ => Self::Substring,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    Reverse,
+    Forward,
+}
+
+#[derive(Debug)]
+struct Match {
+    entry: usize,
+    /// Byte offsets of the matched characters inside the entry.
+    positions: Vec<usize>,
+}
+
+/// Incremental history search state.
+///
+/// Matches are kept ordered from the best (or most recent) one to the worst, so searching
+/// backwards moves further down the list.
+#[derive(Debug)]
+pub struct Search {
+    mode: Mode,
+    direction: Direction,
+    query: String,
+    matches: Vec<Match>,
+    selected: usize,
+    /// The number of columns the search line took when it was last drawn.
+    width: usize,
+}
+
+impl Search {
+    pub fn new(mode: Mode, direction: Direction) -> Self {
+        Self {
+            mode,
+            direction,
+            query: String::new(),
+            matches: Vec::new(),
+            selected: 0,
+            width: 0,
+        }
+    }
+
+    pub fn push(&mut self, history: &History, str: &str) {
+        self.query.push_str(str);
+        self.update(history);
+    }
+
+    pub fn pop(&mut self, history: &History) {
+        self.query.pop();
+        self.update(history);
+    }
+
+    pub fn reverse(&mut self) {
+        self.direction = Direction::Reverse;
+        if self.selected + 1 < self.matches.len() {
+            self.selected += 1;
+        }
+    }
+
+    pub fn forward(&mut self) {
+        self.direction = Direction::Forward;
+        self.selected = self.selected.saturating_sub(1);
+    }
+
+    pub fn candidate<'a>(&self, history: &'a History) -> Option<&'a str> {
+        self.matches
+            .get(self.selected)
+            .map(|m| history.entries()[m.entry].as_str())
+    }
+
+    fn update(&mut self, history: &History) {
+        self.selected = 0;
+        self.matches.clear();
+        if self.query.is_empty() {
+            return;
+        }
+
+        let entries = history.entries();
+        let mut frequencies: HashMap<&str, usize> = HashMap::new();
+        for entry in entries {
+            *frequencies.entry(entry).or_default() += 1;
+        }
+
+        let mut scored: Vec<(usize, Match)> = Vec::new();
+        let mut seen: HashSet<&str> = HashSet::new();
+        for (index, entry) in entries.iter().enumerate().rev() {
+            // Only the most recent occurrence of a command is offered.
+            if !seen.insert(entry) {
+                continue;
+            }
+
+            match self.mode {
+                Mode::Substring => {
+                    if let Some(start) = entry.rfind(&self.query) {
+                        let positions = entry[start..start + self.query.len()]
+                            .char_indices()
+                            .map(|(offset, _)| start + offset)
+                            .collect();
+                        self.matches.push(Match {
+                            entry: index,
+                            positions,
+                        });
+                    }
+                }
+                Mode::Fuzzy => {
+                    if let Some((quality, positions)) = fuzzy_match(entry, &self.query) {
+                        let recency = 10 * (index + 1) / entries.len();
+                        let frequency = 2 * frequencies[entry.as_str()].min(10);
+                        scored.push((
+                            quality + recency + frequency,
+                            Match {
+                                entry: index,
+                                positions,
+                            },
+                        ));
+                    }
+                }
+            }
+        }
+
+        if self.mode == Mode::Fuzzy {
+            // The sort is stable, so equally scored entries stay ordered by recency.
+            scored.sort_by(|(a, _), (b, _)| b.cmp(a));
+            self.matches = scored.into_iter().map(|(_, m)| m).collect();
+        }
+    }
+
+    /// Draws the search mini-prompt in place of the one drawn before, on a terminal `columns`
+    /// wide.
+    pub fn render(
+        &mut self,
+        history: &History,
+        stdout: &mut impl Write,
+        columns: usize,
+    ) -> io::Result<()> {
+        self.erase(stdout, columns)?;
+        let failed = !self.query.is_empty() && self.matches.is_empty();
+        let prompt = format!(
+            "({}{}i-search)`{}': ",
+            if failed { "failed " } else { "" },
+            match self.direction {
+                Direction::Reverse => "reverse-",
+                Direction::Forward => "",
+            },
+            self.query
+        );
+        write!(stdout, "{}", prompt)?;
+        self.width = width::str_width(&prompt);
+
+        if let Some(m) = self.matches.get(self.selected) {
+            let entry = &history.entries()[m.entry];
+            for (offset, c) in entry.char_indices() {
+                let c = if c == '\n' { '␤' } else { c };
+                if m.positions.contains(&offset) {
+                    write!(stdout, "\x1b[7m{}\x1b[27m", c)?;
+                } else {
+                    write!(stdout, "{}", c)?;
+                }
+                self.width += width::char_width(c);
+            }
+        }
+
+        stdout.flush()
+    }
+
+    /// Erases the search line, which may have wrapped, leaving the cursor where it started.
+    pub fn erase(&self, stdout: &mut impl Write, columns: usize) -> io::Result<()> {
+        // The cursor stays on a full row until something more is written.
+        let rows = self.width.saturating_sub(1) / columns.max(1);
+        write!(stdout, "\r")?;
+        if rows > 0 {
+            write!(stdout, "\x1b[{}A", rows)?;
+        }
+        write!(stdout, "\x1b[J")
+    }
+}
+
+/// Matches `query` as a subsequence of `entry`, returning its score and the byte offsets
+/// of the matched characters.
+fn fuzzy_match(entry: &str, query: &str) -> Option<(usize, Vec<usize>)> {
+    let mut query_chars = query.chars().peekable();
+    let mut positions = Vec::new();
+    let mut score: usize = 0;
+    let mut previous: Option<char> = None;
+    let mut consecutive = false;
+
+    for (offset, c) in entry.char_indices() {
+        let Some(&q) = query_chars.peek() else {
+            break;
+        };
+
+        if c.to_lowercase().eq(q.to_lowercase()) {
+            score += 1;
+            if consecutive {
+                score += 5;
+            }
+            if previous.is_none_or(|p| !p.is_alphanumeric()) {
+                score += 3;
+            }
+            if c == q {
+                score += 1;
+            }
+            positions.push(offset);
+            query_chars.next();
+            consecutive = true;
+        } else {
+            consecutive = false;
+        }
+        previous = Some(c);
+    }
+
+    if query_chars.peek().is_some() {
+        return None;
+    }
+
+    // Prefer tight matches over ones scattered along a long command.
+    let span = positions.last()? - positions.first()? + 1;
+    Some((
+        (score * 4).saturating_sub(span.saturating_sub(query.len())),
+        positions,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history(entries: &[&str]) -> History {
+        let mut history = History::default();
+        for entry in entries {
+            history.push(entry).unwrap();
+        }
+        history
+    }
+
+    fn matches<'a>(search: &Search, history: &'a History) -> Vec<&'a str> {
+        search
+            .matches
+            .iter()
+            .map(|m| history.entries()[m.entry].as_str())
+            .collect()
+    }
+
+    #[test]
+    fn substrings_match_the_latest_entries_first_and_once() {
+        let history = history(&["git status", "ls", "git commit", "git status", "echo git"]);
+        let mut search = Search::new(Mode::Substring, Direction::Reverse);
+        search.push(&history, "git");
+        assert_eq!(
+            matches(&search, &history),
+            ["echo git", "git status", "git commit"]
+        );
+        assert_eq!(search.matches[0].positions, [5, 6, 7]);
+
+        assert_eq!(search.candidate(&history), Some("echo git"));
+        search.reverse();
+        search.reverse();
+        search.reverse();
+        assert_eq!(search.candidate(&history), Some("git commit"));
+        search.forward();
+        assert_eq!(search.candidate(&history), Some("git status"));
+        assert_eq!(search.direction, Direction::Forward);
+
+        search.push(&history, " s");
+        assert_eq!(matches(&search, &history), ["git status"]);
+        search.push(&history, "x");
+        assert_eq!(search.candidate(&history), None);
+        search.pop(&history);
+        assert_eq!(search.candidate(&history), Some("git status"));
+    }
+
+    #[test]
+    fn fuzzy_matches_are_ranked_by_score() {
+        let history = history(&[
+            "gst",
+            "grep -r todo",
+            "git status",
+            "git stash",
+            "git status",
+        ]);
+        let mut search = Search::new(Mode::Fuzzy, Direction::Reverse);
+        search.push(&history, "GST");
+        let found = matches(&search, &history);
+        assert_eq!(found[0], "gst");
+        assert_eq!(found.len(), 3);
+        assert!(!found.contains(&"grep -r todo"));
+        assert_eq!(search.matches[0].positions, [0, 1, 2]);
+
+        assert_eq!(fuzzy_match("git status", "gs").unwrap().1, [0, 4]);
+        assert!(fuzzy_match("git status", "gs").unwrap().0 > fuzzy_match("gxs", "gs").unwrap().0);
+        assert_eq!(fuzzy_match("git", "gti"), None);
+    }
+
+    #[test]
+    fn the_mode_is_taken_from_histsearch() {
+        let mut variables = Variables::default();
+        assert_eq!(Mode::from_variables(&variables), Mode::Substring);
+        variables.set("HISTSEARCH", "fuzzy");
+        assert_eq!(Mode::from_variables(&variables), Mode::Fuzzy);
+        variables.set("HISTSEARCH", "other");
+        assert_eq!(Mode::from_variables(&variables), Mode::Substring);
+    }
+
+    #[test]
+    fn the_whole_search_line_is_redrawn_when_it_wraps() {
+        let history = history(&["echo a long command"]);
+        let mut search = Search::new(Mode::Substring, Direction::Reverse);
+        let mut output = Vec::new();
+        search.render(&history, &mut output, 10).unwrap();
+        assert_eq!(output, b"\r\x1b[J(reverse-i-search)`': ");
+
+        // The prompt took 22 columns, the cursor is on its third row.
+        search.push(&history, "long");
+        output.clear();
+        search.render(&history, &mut output, 10).unwrap();
+        assert!(output.starts_with(b"\r\x1b[2A\x1b[J(reverse-i-search)`long': echo a "));
+        assert_eq!(search.width, 45);
+
+        output.clear();
+        search.erase(&mut output, 45).unwrap();
+        assert_eq!(output, b"\r\x1b[J");
+        output.clear();
+        search.erase(&mut output, 44).unwrap();
+        assert_eq!(output, b"\r\x1b[1A\x1b[J");
+    }
+}
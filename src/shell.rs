@@ -0,0 +1,751 @@
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    ffi::{CString, OsString},
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    os::{
+        fd::{AsFd, FromRawFd, OwnedFd},
+        unix::{ffi::OsStringExt, fs::PermissionsExt, process::CommandExt},
+    },
+    path::{Path, PathBuf},
+    process::{self, Stdio},
+    rc::Rc,
+};
+
+use crate::{
+    builtins,
+    complete::Spec,
+    config::Config,
+    expand,
+    history::History,
+    keymap::Keymap,
+    parser::{self, AndOr, Command, CommandKind, List, Pipeline, Redirection},
+    variables::Variables,
+};
+
+/// Standard streams of a command, `None` standing for the ones of the shell itself.
+#[derive(Debug, Clone, Default)]
+pub struct Streams {
+    pub stdin: Option<Rc<File>>,
+    pub stdout: Option<Rc<File>>,
+    pub stderr: Option<Rc<File>>,
+}
+
+impl Streams {
+    pub fn stdout(&self) -> Box<dyn Write + '_> {
+        match &self.stdout {
+            Some(file) => Box::new(&**file),
+            None => Box::new(io::stdout()),
+        }
+    }
+
+    pub fn stderr(&self) -> Box<dyn Write + '_> {
+        match &self.stderr {
+            Some(file) => Box::new(&**file),
+            None => Box::new(io::stderr()),
+        }
+    }
+
+    fn stdio(stream: &Option<Rc<File>>) -> io::Result<Stdio> {
+        Ok(match stream {
+            Some(file) => Stdio::from(file.try_clone()?),
+            None => Stdio::inherit(),
+        })
+    }
+}
+
+enum Outcome {
+    Status(i32),
+    /// A child process, running a command or a subshell.
+    Child(libc::pid_t),
+}
+
+#[derive(Debug)]
+pub struct Shell {
+    pub config: Config,
+    pub history: History,
+    pub variables: Variables,
+    pub functions: HashMap<String, Rc<Command>>,
+    pub aliases: HashMap<String, String>,
+    pub completions: HashMap<String, Spec>,
+    pub keymap: Keymap,
+    /// Commands whose file in the `completions` directory has been looked for.
+    pub searched_completions: HashSet<String>,
+    pub positional: Vec<String>,
+
+    pub status: i32,
+    pub jobs: Vec<libc::pid_t>,
+    pub last_background: Option<libc::pid_t>,
+    /// The process ID of the shell, which `$$` expands to in subshells too.
+    pid: u32,
+
+    /// The interactive line being executed, which `fc` leaves out of the history it edits.
+    pub line: Option<String>,
+    /// Set by the `exit` builtin.
+    pub exit: Option<i32>,
+}
+
+impl Shell {
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            history: History::default(),
+            variables: Variables::from_env(),
+            functions: HashMap::new(),
+            aliases: HashMap::new(),
+            completions: HashMap::new(),
+            keymap: Keymap::default(),
+            searched_completions: HashSet::new(),
+            positional: Vec::new(),
+
+            status: 0,
+            jobs: Vec::new(),
+            last_background: None,
+            pid: process::id(),
+
+            line: None,
+            exit: None,
+        }
+    }
+
+    /// Records an interactively entered line in the history and executes it.
+    pub fn run_line(&mut self, line: &str) -> io::Result<()> {
+        self.record(line);
+        self.line = Some(line.to_owned());
+        let result = self.execute(line, &Streams::default());
+        self.line = None;
+        result
+    }
+
+    /// Enters `line` into the history, warning when it cannot be saved.
+    pub fn record(&mut self, line: &str) {
+        if let Err(error) = self.history.push(line) {
+            eprintln!("oxide-film: {}; the history is no longer saved", error);
+        }
+    }
+
+    /// Executes `source`. An expansion error aborts the rest of it, as a syntax error does.
+    pub fn execute(&mut self, source: &str, streams: &Streams) -> io::Result<()> {
+        match parser::parse(source, &self.aliases) {
+            Ok(list) => match self.run_list(&list, streams) {
+                Ok(status) => self.status = status,
+                Err(error) if expand::is_error(&error) => {
+                    writeln!(streams.stderr(), "oxide-film: {}", error)?;
+                    self.status = 1;
+                }
+                Err(error) => return Err(error),
+            },
+            Err(error) => {
+                writeln!(streams.stderr(), "oxide-film: {}", error)?;
+                self.status = 2;
+            }
+        }
+        Ok(())
+    }
+
+    /// Executes the commands of a file in the current shell.
+    pub fn source(&mut self, path: &Path, streams: &Streams) -> io::Result<()> {
+        let source = fs::read_to_string(path)?;
+        self.execute(&source, streams)
+    }
+
+    /// Executes `source` and returns its output without the trailing newlines, as for
+    /// command substitution.
+    ///
+    /// The output goes to a file rather than a pipe, which nothing could read while the shell
+    /// process is busy running the commands.
+    pub fn capture(&mut self, source: &str) -> io::Result<String> {
+        let (file, path) = create_temp_file("oxide-film-output-", "")?;
+        fs::remove_file(&path)?;
+        let file = Rc::new(file);
+
+        let streams = Streams {
+            stdout: Some(file.clone()),
+            ..Streams::default()
+        };
+        self.execute(source, &streams)?;
+        drop(streams);
+
+        let mut output = Vec::new();
+        (&*file).seek(SeekFrom::Start(0))?;
+        (&*file).read_to_end(&mut output)?;
+        let mut output = String::from_utf8_lossy(&output).into_owned();
+        output.truncate(output.trim_end_matches('\n').len());
+
+        Ok(output)
+    }
+
+    pub fn parameter(&self, name: &str) -> Option<String> {
+        match name {
+            "?" => Some(self.status.to_string()),
+            "$" => Some(self.pid.to_string()),
+            "!" => self.last_background.map(|pid| pid.to_string()),
+            "#" => Some(self.positional.len().to_string()),
+            "@" | "*" => Some(self.positional.join(" ")),
+            "-" => Some(String::from("i")),
+            "0" => Some(String::from("oxide-film")),
+            _ if name.bytes().all(|b| b.is_ascii_digit()) => {
+                let index: usize = name.parse().ok()?;
+                self.positional.get(index.checked_sub(1)?).cloned()
+            }
+            _ => self.variables.get(name).map(str::to_owned),
+        }
+    }
+
+    pub fn find_executable(&self, name: &str) -> Option<PathBuf> {
+        if name.contains('/') {
+            return Some(PathBuf::from(name));
+        }
+
+        self.variables
+            .get("PATH")?
+            .split(':')
+            .map(|directory| Path::new(if directory.is_empty() { "." } else { directory }))
+            .map(|directory| directory.join(name))
+            .find(|path| is_executable(path))
+    }
+
+    /// Forgets the background commands that have finished.
+    pub fn reap_jobs(&mut self) {
+        self.jobs
+            .retain(|&pid| unsafe { libc::waitpid(pid, std::ptr::null_mut(), libc::WNOHANG) } == 0);
+    }
+
+    pub fn run_list(&mut self, list: &List, streams: &Streams) -> io::Result<i32> {
+        let mut status = 0;
+        for (and_or, background) in list {
+            if self.exit.is_some() {
+                break;
+            }
+
+            status = if *background && and_or.rest.is_empty() {
+                self.run_pipeline(&and_or.first, streams, true)?
+            } else {
+                self.run_and_or(and_or, streams)?
+            };
+            self.status = status;
+        }
+        Ok(status)
+    }
+
+    fn run_and_or(&mut self, and_or: &AndOr, streams: &Streams) -> io::Result<i32> {
+        let mut status = self.run_pipeline(&and_or.first, streams, false)?;
+        for (and, pipeline) in &and_or.rest {
+            if self.exit.is_some() {
+                break;
+            }
+            if (status == 0) == *and {
+                self.status = status;
+                status = self.run_pipeline(pipeline, streams, false)?;
+            }
+        }
+        Ok(status)
+    }
+
+    fn run_pipeline(
+        &mut self,
+        pipeline: &Pipeline,
+        streams: &Streams,
+        background: bool,
+    ) -> io::Result<i32> {
+        let count = pipeline.commands.len();
+        let mut children = Vec::new();
+        let mut status = 0;
+        let mut stdin = streams.stdin.clone();
+
+        for (index, command) in pipeline.commands.iter().enumerate() {
+            let last = index + 1 == count;
+            let mut stage = Streams {
+                stdin: stdin.take(),
+                ..streams.clone()
+            };
+            if !last {
+                let (reader, writer) = pipe()?;
+                stage.stdout = Some(Rc::new(writer));
+                stdin = Some(Rc::new(reader));
+            }
+
+            // A command the shell runs itself gets a process of its own too, so that it runs
+            // at the same time as the rest of the pipeline.
+            if !last && self.runs_in_process(command) {
+                let pid = self.fork(streams, |shell| {
+                    // The next command reading the pipe must be the only reader left.
+                    drop(stdin.take());
+                    match shell.run_command(command, stage, true)? {
+                        Outcome::Status(status) => Ok(status),
+                        Outcome::Child(pid) => shell.wait(pid),
+                    }
+                })?;
+                children.push((last, pid));
+                continue;
+            }
+
+            match self.run_command(command, stage, false)? {
+                Outcome::Status(code) if last => status = code,
+                Outcome::Status(_) => {}
+                Outcome::Child(child) => children.push((last, child)),
+            }
+        }
+
+        if background {
+            self.last_background = children.last().map(|&(_, pid)| pid);
+            self.jobs.extend(children.into_iter().map(|(_, pid)| pid));
+            return Ok(0);
+        }
+
+        for (last, pid) in children {
+            let code = self.wait(pid)?;
+            if last {
+                status = code;
+            }
+        }
+
+        Ok(match pipeline.negated {
+            true => (status == 0) as i32,
+            false => status,
+        })
+    }
+
+    /// Whether the command runs inside the shell process rather than in a child process.
+    fn runs_in_process(&self, command: &Command) -> bool {
+        match &command.kind {
+            CommandKind::Simple { words, .. } => words.first().is_none_or(|name| {
+                self.functions.contains_key(name) || builtins::find(name).is_some()
+            }),
+            CommandKind::Subshell(_) => false,
+            _ => true,
+        }
+    }
+
+    fn run_command(
+        &mut self,
+        command: &Command,
+        streams: Streams,
+        wait: bool,
+    ) -> io::Result<Outcome> {
+        let Some(streams) = self.redirect(&command.redirections, streams)? else {
+            return Ok(Outcome::Status(1));
+        };
+
+        let status = match &command.kind {
+            CommandKind::Simple { assignments, words } => {
+                return self.run_simple(assignments, words, streams, wait);
+            }
+            CommandKind::Group(list) => self.run_list(list, &streams)?,
+            CommandKind::Subshell(list) => return self.run_subshell(list, &streams, wait),
+            CommandKind::If {
+                branches,
+                otherwise,
+            } => {
+                let mut status = None;
+                for (condition, body) in branches {
+                    if self.run_list(condition, &streams)? == 0 {
+                        status = Some(self.run_list(body, &streams)?);
+                        break;
+                    }
+                }
+                match (status, otherwise) {
+                    (Some(status), _) => status,
+                    (None, Some(otherwise)) => self.run_list(otherwise, &streams)?,
+                    (None, None) => 0,
+                }
+            }
+            CommandKind::Loop {
+                until,
+                condition,
+                body,
+            } => {
+                let mut status = 0;
+                while self.exit.is_none() && (self.run_list(condition, &streams)? == 0) != *until {
+                    status = self.run_list(body, &streams)?;
+                }
+                status
+            }
+            CommandKind::For { name, words, body } => {
+                let items = match words {
+                    Some(words) => {
+                        let mut items = Vec::new();
+                        for word in words {
+                            items.extend(expand::fields(self, word)?);
+                        }
+                        items
+                    }
+                    None => self.positional.clone(),
+                };
+
+                let mut status = 0;
+                for item in items {
+                    if self.exit.is_some() {
+                        break;
+                    }
+                    self.variables.set(name, item);
+                    status = self.run_list(body, &streams)?;
+                }
+                status
+            }
+            CommandKind::Function { name, body } => {
+                self.functions
+                    .insert(name.clone(), Rc::new((**body).clone()));
+                0
+            }
+        };
+
+        Ok(Outcome::Status(status))
+    }
+
+    fn run_simple(
+        &mut self,
+        assignments: &[String],
+        words: &[String],
+        streams: Streams,
+        wait: bool,
+    ) -> io::Result<Outcome> {
+        let mut fields = Vec::new();
+        for word in words {
+            fields.extend(expand::fields(self, word)?);
+        }
+        let mut values = Vec::new();
+        for assignment in assignments {
+            let (name, value) = assignment.split_once('=').unwrap_or((assignment, ""));
+            values.push((name.to_owned(), expand::string(self, value)?));
+        }
+
+        let Some(name) = fields.first().cloned() else {
+            for (name, value) in values {
+                self.variables.set(&name, value);
+            }
+            return Ok(Outcome::Status(0));
+        };
+
+        if let Some(function) = self.functions.get(&name).cloned() {
+            for (name, value) in values {
+                self.variables.set(&name, value);
+            }
+            let positional = std::mem::replace(&mut self.positional, fields[1..].to_vec());
+            let outcome = self.run_command(&function, streams, true);
+            self.positional = positional;
+            return outcome;
+        }
+
+        if let Some(builtin) = builtins::find(&name) {
+            for (name, value) in values {
+                self.variables.set(&name, value);
+            }
+            return Ok(Outcome::Status(builtin(self, &fields, &streams)?));
+        }
+
+        let Some(path) = self.find_executable(&name) else {
+            writeln!(streams.stderr(), "oxide-film: {}: command not found", name)?;
+            return Ok(Outcome::Status(127));
+        };
+
+        let mut command = process::Command::new(path);
+        command
+            .arg0(&name)
+            .args(&fields[1..])
+            .env_clear()
+            .envs(self.variables.exported())
+            .envs(values)
+            .stdin(Streams::stdio(&streams.stdin)?)
+            .stdout(Streams::stdio(&streams.stdout)?)
+            .stderr(Streams::stdio(&streams.stderr)?);
+        unsafe {
+            command.pre_exec(|| {
+                reset_signals();
+                Ok(())
+            });
+        }
+
+        match command.spawn() {
+            Ok(child) if wait => Ok(Outcome::Status(self.wait(child.id() as libc::pid_t)?)),
+            Ok(child) => Ok(Outcome::Child(child.id() as libc::pid_t)),
+            Err(error) => {
+                writeln!(streams.stderr(), "oxide-film: {}: {}", name, error)?;
+                Ok(Outcome::Status(match error.kind() {
+                    io::ErrorKind::NotFound => 127,
+                    _ => 126,
+                }))
+            }
+        }
+    }
+
+    /// Runs `list` in a forked copy of the shell, so that its changes to variables, functions
+    /// and the working directory do not outlive it.
+    fn run_subshell(&mut self, list: &List, streams: &Streams, wait: bool) -> io::Result<Outcome> {
+        let pid = self.fork(streams, |shell| shell.run_list(list, streams))?;
+        match wait {
+            true => Ok(Outcome::Status(self.wait(pid)?)),
+            false => Ok(Outcome::Child(pid)),
+        }
+    }
+
+    /// Forks a copy of the shell that runs `body` and exits with its status, and returns its
+    /// process ID.
+    ///
+    /// The copy goes on running the shell without exec'ing, which is only sound as long as the
+    /// shell runs no threads.
+    fn fork(
+        &mut self,
+        streams: &Streams,
+        body: impl FnOnce(&mut Self) -> io::Result<i32>,
+    ) -> io::Result<libc::pid_t> {
+        // Output buffered before the fork would otherwise be written twice.
+        io::stdout().flush()?;
+        io::stderr().flush()?;
+
+        let pid = unsafe { libc::fork() };
+        if pid == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        if pid == 0 {
+            unsafe {
+                reset_signals();
+                // Like the commands it runs, the copy ends when the reader of its output does.
+                libc::signal(libc::SIGPIPE, libc::SIG_DFL);
+            }
+            let status = match body(self) {
+                Ok(status) => self.exit.unwrap_or(status),
+                Err(error) => {
+                    let _ = writeln!(streams.stderr(), "oxide-film: {}", error);
+                    1
+                }
+            };
+            let _ = io::stdout().flush();
+            unsafe { libc::_exit(status) };
+        }
+        Ok(pid)
+    }
+
+    /// Waits for a child process to end or stop, and returns its exit status, or 128 plus the
+    /// number of the signal that killed or stopped it. A stopped child is kept as a job.
+    fn wait(&mut self, pid: libc::pid_t) -> io::Result<i32> {
+        let mut status = 0;
+        while unsafe { libc::waitpid(pid, &mut status, libc::WUNTRACED) } == -1 {
+            let error = io::Error::last_os_error();
+            if error.kind() != io::ErrorKind::Interrupted {
+                return Err(error);
+            }
+        }
+        if libc::WIFSTOPPED(status) {
+            writeln!(io::stderr(), "\noxide-film: {}: stopped", pid)?;
+            self.jobs.push(pid);
+            return Ok(128 + libc::WSTOPSIG(status));
+        }
+        Ok(match libc::WIFEXITED(status) {
+            true => libc::WEXITSTATUS(status),
+            false => 128 + libc::WTERMSIG(status),
+        })
+    }
+
+    /// Applies redirections to `streams`, returning `None` if one of them failed.
+    fn redirect(
+        &mut self,
+        redirections: &[Redirection],
+        mut streams: Streams,
+    ) -> io::Result<Option<Streams>> {
+        for redirection in redirections {
+            let target = expand::string(self, &redirection.target)?;
+            let fd = redirection
+                .fd
+                .unwrap_or(match redirection.operator.starts_with('<') {
+                    true => 0,
+                    false => 1,
+                });
+
+            let file = match redirection.operator.as_str() {
+                "<" => File::open(&target),
+                ">" | ">|" => File::create(&target),
+                ">>" => OpenOptions::new().append(true).create(true).open(&target),
+                "<>" => OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .create(true)
+                    .truncate(false)
+                    .open(&target),
+                _ => match target.as_str() {
+                    "0" => stream(&streams.stdin, io::stdin().as_fd()),
+                    "1" => stream(&streams.stdout, io::stdout().as_fd()),
+                    "2" => stream(&streams.stderr, io::stderr().as_fd()),
+                    _ => Err(io::Error::other("bad file descriptor")),
+                },
+            };
+
+            let file = match file {
+                Ok(file) => Rc::new(file),
+                Err(error) => {
+                    writeln!(streams.stderr(), "oxide-film: {}: {}", target, error)?;
+                    return Ok(None);
+                }
+            };
+            match fd {
+                0 => streams.stdin = Some(file),
+                1 => streams.stdout = Some(file),
+                2 => streams.stderr = Some(file),
+                _ => {
+                    writeln!(streams.stderr(), "oxide-film: {}: bad file descriptor", fd)?;
+                    return Ok(None);
+                }
+            }
+        }
+
+        Ok(Some(streams))
+    }
+}
+
+pub fn is_executable(path: &Path) -> bool {
+    path.metadata()
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+fn stream(stream: &Option<Rc<File>>, default: std::os::fd::BorrowedFd) -> io::Result<File> {
+    match stream {
+        Some(file) => file.try_clone(),
+        None => Ok(File::from(default.try_clone_to_owned()?)),
+    }
+}
+
+/// Restores the keyboard signals the shell ignores, which its children would inherit.
+///
+/// # Safety
+///
+/// Must only be called in a child process, between `fork` and `exec` or `_exit`.
+unsafe fn reset_signals() {
+    unsafe {
+        libc::signal(libc::SIGINT, libc::SIG_DFL);
+        libc::signal(libc::SIGQUIT, libc::SIG_DFL);
+        libc::signal(libc::SIGTSTP, libc::SIG_DFL);
+    }
+}
+
+/// Creates a file in the temporary directory that only the user can read and write, with a
+/// name no other file has, and returns it with its path.
+pub fn create_temp_file(prefix: &str, suffix: &str) -> io::Result<(File, PathBuf)> {
+    let template = env::temp_dir().join(format!("{}XXXXXX{}", prefix, suffix));
+    let mut template = CString::new(template.into_os_string().into_vec())?.into_bytes_with_nul();
+    let fd = unsafe { libc::mkstemps(template.as_mut_ptr().cast(), suffix.len() as libc::c_int) };
+    if fd == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    let file = unsafe { File::from_raw_fd(fd) };
+    // Like the files the standard library opens, it is not left open in the commands run.
+    if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    template.pop();
+    Ok((file, PathBuf::from(OsString::from_vec(template))))
+}
+
+fn pipe() -> io::Result<(File, File)> {
+    let (reader, writer) = io::pipe()?;
+    Ok((
+        File::from(OwnedFd::from(reader)),
+        File::from(OwnedFd::from(writer)),
+    ))
+}
+
+#[cfg(test)]
+impl Shell {
+    /// Returns a shell with no variables but `PATH`, whose history is kept in memory.
+    pub fn for_tests() -> Self {
+        let config = Config {
+            home: PathBuf::from("/nonexistent"),
+            init_file: PathBuf::from("/nonexistent/init.sh"),
+            history_file: PathBuf::from("/dev/null"),
+        };
+        let mut shell = Self::new(config);
+        shell.variables = Variables::default();
+        shell
+            .variables
+            .set("PATH", std::env::var("PATH").unwrap_or_default());
+        shell
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subshells_keep_their_changes_to_themselves() {
+        let mut shell = Shell::for_tests();
+        let directory = env::current_dir().unwrap();
+        assert_eq!(
+            shell
+                .capture("x=0; (x=1; cd /; echo $x; pwd); echo $x; pwd")
+                .unwrap(),
+            format!("1\n/\n0\n{}", directory.display())
+        );
+        assert_eq!(
+            shell
+                .capture("(exit 3); echo $?; (false) || echo $?")
+                .unwrap(),
+            "3\n1"
+        );
+        assert_eq!(
+            shell.capture("(echo a; echo b) | tr a-z A-Z").unwrap(),
+            "A\nB"
+        );
+    }
+
+    #[test]
+    fn pipeline_stages_run_at_the_same_time() {
+        let mut shell = Shell::for_tests();
+        // The loop only ends when the pipe to `head` breaks.
+        assert_eq!(
+            shell
+                .capture("alias y=yes; while :; do alias; done | head -n 1")
+                .unwrap(),
+            "alias y=yes"
+        );
+        assert_eq!(
+            shell.capture("f() { alias; }; f | tr a-z A-Z").unwrap(),
+            "ALIAS Y=YES"
+        );
+        assert_eq!(
+            shell
+                .capture("x=0; x=1 | :; echo $x; exit 4 | true; echo $?")
+                .unwrap(),
+            "0\n0"
+        );
+        assert_eq!(shell.capture("true | false; echo $?").unwrap(), "1");
+    }
+
+    #[test]
+    fn children_do_not_inherit_ignored_job_control_signals() {
+        unsafe {
+            libc::signal(libc::SIGINT, libc::SIG_IGN);
+            libc::signal(libc::SIGQUIT, libc::SIG_IGN);
+            libc::signal(libc::SIGTSTP, libc::SIG_IGN);
+        }
+        let mut shell = Shell::for_tests();
+
+        // The forked copies of the shell report the signals they ignore through their status.
+        let pid = shell
+            .fork(&Streams::default(), |_| {
+                let ignored = [libc::SIGINT, libc::SIGQUIT, libc::SIGTSTP]
+                    .into_iter()
+                    .filter(|&signal| unsafe {
+                        let mut action: libc::sigaction = std::mem::zeroed();
+                        libc::sigaction(signal, std::ptr::null(), &mut action);
+                        action.sa_sigaction == libc::SIG_IGN
+                    })
+                    .count();
+                Ok(ignored as i32)
+            })
+            .unwrap();
+        assert_eq!(shell.wait(pid).unwrap(), 0);
+
+        assert_eq!(
+            shell.capture("sh -c 'kill -INT $$'; echo $?").unwrap(),
+            "130"
+        );
+        assert_eq!(
+            shell
+                .capture("(sh -c 'kill -INT $PPID'; echo survived); echo $?")
+                .unwrap(),
+            "130"
+        );
+    }
+}
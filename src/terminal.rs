@@ -0,0 +1,98 @@
+use std::{
+    io::{self, PipeReader},
+    os::fd::{AsRawFd, IntoRawFd, OwnedFd, RawFd},
+    sync::atomic::{AtomicI32, Ordering},
+};
+
+/// The pipe the SIGWINCH handler writes to.
+static RESIZE_PIPE: AtomicI32 = AtomicI32::new(-1);
+
+extern "C" fn on_resize(_: libc::c_int) {
+    unsafe {
+        libc::write(
+            RESIZE_PIPE.load(Ordering::Relaxed),
+            [0u8].as_ptr().cast(),
+            1,
+        );
+    }
+}
+
+/// Returns a pipe that a byte is written to whenever the terminal is resized.
+///
+/// Interrupted system calls are restarted, so a resize only shows up when the pipe is polled.
+pub fn resizes() -> io::Result<PipeReader> {
+    let (reader, writer) = io::pipe()?;
+    let writer = OwnedFd::from(writer).into_raw_fd();
+    unsafe {
+        // The handler must not block on a full pipe, and draining it must not block either.
+        for fd in [reader.as_raw_fd(), writer] {
+            if libc::fcntl(fd, libc::F_SETFL, libc::O_NONBLOCK) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        RESIZE_PIPE.store(writer, Ordering::Relaxed);
+
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = on_resize as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        if libc::sigaction(libc::SIGWINCH, &action, std::ptr::null_mut()) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(reader)
+}
+
+/// Switches the controlling terminal between the raw mode used by the line editor and the
+/// mode it had on startup, which commands are executed in.
+pub struct Terminal {
+    fd: RawFd,
+    original: libc::termios,
+    raw: libc::termios,
+}
+
+impl Terminal {
+    pub fn new(fd: RawFd) -> io::Result<Self> {
+        unsafe {
+            let mut original: libc::termios = std::mem::zeroed();
+
+            if libc::tcgetattr(fd, &mut original) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let mut raw = original;
+            libc::cfmakeraw(&mut raw);
+
+            Ok(Self { fd, original, raw })
+        }
+    }
+
+    pub fn raw(&self) -> io::Result<()> {
+        self.set(&self.raw)
+    }
+
+    pub fn cooked(&self) -> io::Result<()> {
+        self.set(&self.original)
+    }
+
+    /// Returns the number of columns and rows of the terminal.
+    pub fn size(&self) -> io::Result<(usize, usize)> {
+        unsafe {
+            let mut size: libc::winsize = std::mem::zeroed();
+            if libc::ioctl(self.fd, libc::TIOCGWINSZ, &mut size) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok((size.ws_col as usize, size.ws_row as usize))
+        }
+    }
+
+    fn set(&self, settings: &libc::termios) -> io::Result<()> {
+        unsafe {
+            // TCSADRAIN lets the output written in the previous mode reach the terminal first.
+            if libc::tcsetattr(self.fd, libc::TCSADRAIN, settings) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,78 @@
+use std::{collections::HashMap, env};
+
+#[derive(Debug, Clone)]
+struct Variable {
+    value: String,
+    exported: bool,
+}
+
+/// Shell variables, the exported ones making up the environment of executed commands.
+#[derive(Debug, Default)]
+pub struct Variables {
+    values: HashMap<String, Variable>,
+}
+
+impl Variables {
+    pub fn from_env() -> Self {
+        Self {
+            values: env::vars()
+                .map(|(name, value)| {
+                    (
+                        name,
+                        Variable {
+                            value,
+                            exported: true,
+                        },
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.values
+            .get(name)
+            .map(|variable| variable.value.as_str())
+    }
+
+    pub fn set(&mut self, name: &str, value: impl Into<String>) {
+        let value = value.into();
+        match self.values.get_mut(name) {
+            Some(variable) => variable.value = value,
+            None => {
+                self.values.insert(
+                    name.to_owned(),
+                    Variable {
+                        value,
+                        exported: false,
+                    },
+                );
+            }
+        }
+    }
+
+    pub fn export(&mut self, name: &str) {
+        self.values
+            .entry(name.to_owned())
+            .or_insert_with(|| Variable {
+                value: String::new(),
+                exported: false,
+            })
+            .exported = true;
+    }
+
+    pub fn unset(&mut self, name: &str) {
+        self.values.remove(name);
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.values.keys().map(String::as_str)
+    }
+
+    pub fn exported(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.values
+            .iter()
+            .filter(|(_, variable)| variable.exported)
+            .map(|(name, variable)| (name.as_str(), variable.value.as_str()))
+    }
+}
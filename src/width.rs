@@ -0,0 +1,29 @@
+use std::ffi::{CStr, c_int};
+
+unsafe extern "C" {
+    fn wcwidth(c: libc::wchar_t) -> c_int;
+}
+
+/// Selects the locale of the environment for the character classification functions, or
+/// `C.UTF-8` if it does not use UTF-8, so that `wcwidth` knows every character.
+pub fn set_locale() {
+    unsafe {
+        libc::setlocale(libc::LC_CTYPE, c"".as_ptr());
+        let codeset = CStr::from_ptr(libc::nl_langinfo(libc::CODESET));
+        if !codeset.to_bytes().eq_ignore_ascii_case(b"UTF-8") {
+            libc::setlocale(libc::LC_CTYPE, c"C.UTF-8".as_ptr());
+        }
+    }
+}
+
+/// Returns the number of columns the terminal uses to display `c`.
+pub fn char_width(c: char) -> usize {
+    match unsafe { wcwidth(c as libc::wchar_t) } {
+        -1 => 1,
+        width => width as usize,
+    }
+}
+
+pub fn str_width(str: &str) -> usize {
+    str.chars().map(char_width).sum()
+}